@@ -0,0 +1,280 @@
+use std::sync::Arc;
+use crate::audio::mixer::{SoundMixer, Sound, SoundId, PlaybackBuilder, PlaybackStyle, Volume};
+use crate::audio::source::{SoundSource, SampleSource};
+use crate::audio::bus::BusId;
+use crate::audio::volume::VolumeError;
+use crate::audio::rng::XorShift32;
+
+/// Handle of an emitter added to an [`AmbientSystem`]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct EmitterId(usize);
+
+/// Where an ambient sound comes from, in map coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmitterShape {
+    /// a single spot, e.g. a campfire
+    Point { x: f32, y: f32 },
+    /// a rectangle heard at full volume from anywhere inside, e.g. a forest or a stretch of river
+    Area { x: f32, y: f32, width: f32, height: f32 }
+}
+impl EmitterShape {
+    fn distance_to(&self, x: f32, y: f32) -> f32 {
+        let (dx, dy) = match *self {
+            EmitterShape::Point { x: px, y: py } => (x - px, y - py),
+            EmitterShape::Area { x: ax, y: ay, width, height } => (
+                (ax - x).max(x - (ax + width)).max(0.0),
+                (ay - y).max(y - (ay + height)).max(0.0)
+            )
+        };
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// A looping ambient sound placed on the map
+#[derive(Clone)]
+pub struct AmbientEmitter {
+    shape: EmitterShape,
+    sound: Arc<Sound>,
+    volume: Volume,
+    inner_radius: f32,
+    radius: f32
+}
+impl AmbientEmitter {
+    pub fn new(shape: EmitterShape, sound: Arc<Sound>) -> Self {
+        Self {
+            shape,
+            sound,
+            volume: Volume(1.0),
+            inner_radius: 0.0,
+            radius: 500.0
+        }
+    }
+    pub fn point(x: f32, y: f32, sound: Arc<Sound>) -> Self {
+        Self::new(EmitterShape::Point { x, y }, sound)
+    }
+    pub fn area(x: f32, y: f32, width: f32, height: f32, sound: Arc<Sound>) -> Self {
+        Self::new(EmitterShape::Area { x, y, width, height }, sound)
+    }
+    pub fn with_volume(self, volume: Volume) -> Result<Self, VolumeError> {
+        Ok(Self {
+            volume: volume.validate()?,
+            ..self
+        })
+    }
+    /// The emitter is at full volume up to `inner_radius` away from the camera,
+    /// fades linearly and goes silent at `radius`
+    pub fn with_radius(self, inner_radius: f32, radius: f32) -> Self {
+        Self {
+            inner_radius: inner_radius.max(0.0),
+            radius: radius.max(inner_radius.max(0.0)),
+            ..self
+        }
+    }
+
+    /// `0.0..=1.0` depending on the distance between the emitter and the camera
+    fn weight(&self, camera: (f32, f32)) -> f32 {
+        let distance = self.shape.distance_to(camera.0, camera.1);
+        if distance <= self.inner_radius {
+            1.0
+        } else if distance >= self.radius {
+            0.0
+        } else {
+            1.0 - (distance - self.inner_radius) / (self.radius - self.inner_radius)
+        }
+    }
+}
+
+struct EmitterState {
+    emitter: AmbientEmitter,
+    sound_id: Option<SoundId>,
+    weight: f32,
+    fade: f32,
+    audible: bool,
+    removed: bool,
+    applied_volume: f32
+}
+
+/// Starts and stops looping [`AmbientEmitter`]s as the camera moves around the map.
+///
+/// Emitters within audible range fade in, the ones left behind fade out, and only the loudest
+/// `max_voices` play at once. Like [`crate::audio::MusicPlayer`], it is driven by calling
+/// [`AmbientSystem::frame`] every frame with the time passed in milliseconds.
+pub struct AmbientSystem {
+    emitters: Vec<Option<EmitterState>>,
+    camera: (f32, f32),
+    max_voices: usize,
+    fade_ms: usize,
+    bus: BusId,
+    volume: Volume,
+    rng: XorShift32
+}
+impl Default for AmbientSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl AmbientSystem {
+    pub fn new() -> Self {
+        Self {
+            emitters: Vec::new(),
+            camera: (0.0, 0.0),
+            max_voices: 8,
+            fade_ms: 1000,
+            bus: BusId::MASTER,
+            volume: Volume(1.0),
+            rng: XorShift32::from_time()
+        }
+    }
+    /// Most emitters playing at once, not counting the ones fading out
+    pub fn with_max_voices(self, max_voices: usize) -> Self {
+        Self {
+            max_voices,
+            ..self
+        }
+    }
+    pub fn with_fade_ms(self, fade_ms: usize) -> Self {
+        Self {
+            fade_ms,
+            ..self
+        }
+    }
+    /// Bus every emitter plays through, e.g. an "ambience" bus
+    pub fn with_bus(self, bus: BusId) -> Self {
+        Self {
+            bus,
+            ..self
+        }
+    }
+
+    pub fn add(&mut self, emitter: AmbientEmitter) -> EmitterId {
+        let state = EmitterState {
+            emitter,
+            sound_id: None,
+            weight: 0.0,
+            fade: 0.0,
+            audible: false,
+            removed: false,
+            applied_volume: 0.0
+        };
+        // slots of removed emitters are not reused, so a stale id never points at another emitter
+        self.emitters.push(Some(state));
+        EmitterId(self.emitters.len() - 1)
+    }
+    /// Fades the emitter out and forgets it
+    pub fn remove(&mut self, id: EmitterId) {
+        if let Some(Some(state)) = self.emitters.get_mut(id.0) {
+            state.removed = true;
+        }
+    }
+    /// Moves an emitter, e.g. one attached to a unit
+    pub fn set_shape(&mut self, id: EmitterId, shape: EmitterShape) {
+        if let Some(Some(state)) = self.emitters.get_mut(id.0) {
+            state.emitter.shape = shape;
+        }
+    }
+    pub fn set_camera(&mut self, x: f32, y: f32) {
+        self.camera = (x, y);
+    }
+    pub fn set_volume(&mut self, volume: Volume) -> Result<(), VolumeError> {
+        self.volume = volume.validate()?;
+        Ok(())
+    }
+    /// Emitters with a voice in the mixer, including the ones fading out
+    pub fn active_count(&self) -> usize {
+        self.emitters.iter().flatten().filter(|state| state.sound_id.is_some()).count()
+    }
+    /// Stops every voice right away, e.g. when leaving the map. Emitters start over on the next frame.
+    pub fn stop_all(&mut self, mixer: &mut SoundMixer) {
+        for state in self.emitters.iter_mut().flatten() {
+            if let Some(sound_id) = state.sound_id.take() {
+                mixer.stop(sound_id);
+            }
+            state.fade = 0.0;
+        }
+        for slot in self.emitters.iter_mut() {
+            if slot.as_ref().map(|state| state.removed).unwrap_or(false) {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn frame(&mut self, mixer: &mut SoundMixer, delta_time: f32) {
+        let camera = self.camera;
+        let mut candidates = Vec::new();
+        for (index, state) in self.emitters.iter_mut().enumerate() {
+            if let Some(state) = state {
+                state.audible = false;
+                let weight = state.emitter.weight(camera);
+                if weight > 0.0 && !state.removed {
+                    candidates.push((index, weight));
+                }
+            }
+        }
+        // the loudest emitters win when there are more than the cap allows
+        candidates.sort_by(|a, b| {
+            let loudness = |(index, weight): &(usize, f32)| {
+                weight * self.emitters[*index].as_ref().map(|state| state.emitter.volume.0).unwrap_or(0.0)
+            };
+            loudness(b).partial_cmp(&loudness(a)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        for (index, weight) in candidates.into_iter().take(self.max_voices) {
+            if let Some(state) = &mut self.emitters[index] {
+                state.audible = true;
+                // emitters fading out keep their last weight so they don't jump
+                state.weight = weight;
+            }
+        }
+
+        let fade_step = if self.fade_ms == 0 {
+            1.0
+        } else {
+            delta_time / self.fade_ms as f32
+        };
+        let volume = self.volume.0;
+        let bus = self.bus;
+        for slot in self.emitters.iter_mut() {
+            let state = match slot {
+                Some(state) => state,
+                None => continue
+            };
+            if state.audible {
+                state.fade = (state.fade + fade_step).min(1.0);
+                if state.sound_id.is_none() {
+                    state.sound_id = start(mixer, &state.emitter, bus, &mut self.rng);
+                    state.applied_volume = 0.0;
+                }
+            } else {
+                state.fade = (state.fade - fade_step).max(0.0);
+            }
+
+            if let Some(sound_id) = state.sound_id {
+                if state.fade <= 0.0 {
+                    mixer.stop(sound_id);
+                    state.sound_id = None;
+                } else {
+                    let target = volume * state.emitter.volume.0 * state.weight * state.fade;
+                    if target != state.applied_volume {
+                        mixer.set_volume_clamped(sound_id, target);
+                        state.applied_volume = target;
+                    }
+                }
+            }
+            if state.removed && state.sound_id.is_none() {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// starts an emitter at a random point of its loop, so copies of the same sound don't play in unison
+fn start(mixer: &mut SoundMixer, emitter: &AmbientEmitter, bus: BusId, rng: &mut XorShift32) -> Option<SoundId> {
+    let mut source = SampleSource::shared(emitter.sound.clone()).with_playback_style(PlaybackStyle::Looped);
+    let frames = emitter.sound.frame_count();
+    if frames > 0 {
+        source.seek(rng.next_below(frames));
+    }
+    mixer.play(PlaybackBuilder::new()
+        .with_source(source)
+        .with_volume(Volume(0.0))
+        .with_bus(bus))
+}
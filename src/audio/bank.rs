@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::audio::mixer::{SoundMixer, Sound, SoundId, PlaybackBuilder, PlaybackStyle};
+use crate::audio::loudness::{Normalization, NORMALIZATION_CEILING_DB};
+use crate::audio::volume::Gain;
+
+/// Handle of an asset registered in a [`SoundBank`]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct AssetId(usize);
+
+/// Looks up an asset either by its name or by its [`AssetId`]
+#[derive(Clone, Copy, Debug)]
+pub enum AssetKey<'a> {
+    Name(&'a str),
+    Id(AssetId)
+}
+impl<'a> From<&'a str> for AssetKey<'a> {
+    fn from(name: &'a str) -> Self {
+        AssetKey::Name(name)
+    }
+}
+impl<'a> From<AssetId> for AssetKey<'a> {
+    fn from(id: AssetId) -> Self {
+        AssetKey::Id(id)
+    }
+}
+
+#[derive(Clone)]
+enum AssetData {
+    Bytes(Arc<[u8]>),
+    File(PathBuf)
+}
+
+/// Description of a WAV asset to register in a [`SoundBank`]
+#[derive(Clone)]
+pub struct SoundAsset {
+    data: AssetData,
+    group: String,
+    playback_style: PlaybackStyle,
+    normalization: Option<Normalization>
+}
+impl SoundAsset {
+    pub fn from_bytes(bytes: impl Into<Arc<[u8]>>) -> Self {
+        Self::new(AssetData::Bytes(bytes.into()))
+    }
+    /// The file is only read when the asset is decoded
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        Self::new(AssetData::File(path.into()))
+    }
+    fn new(data: AssetData) -> Self {
+        Self {
+            data,
+            group: String::new(),
+            playback_style: PlaybackStyle::Once,
+            normalization: None
+        }
+    }
+    /// Group for [`SoundBank::unload_group`], e.g. a map or a menu
+    pub fn with_group(self, group: &str) -> Self {
+        Self {
+            group: group.to_string(),
+            ..self
+        }
+    }
+    pub fn with_playback_style(self, playback_style: PlaybackStyle) -> Self {
+        Self {
+            playback_style,
+            ..self
+        }
+    }
+    /// Measures the asset when it is decoded and brings it to a target loudness
+    pub fn with_normalization(self, normalization: Normalization) -> Self {
+        Self {
+            normalization: Some(normalization),
+            ..self
+        }
+    }
+}
+
+struct BankEntry {
+    name: String,
+    asset: SoundAsset,
+    decoded: Option<Arc<Sound>>,
+    /// gain of an asset normalized at play time, measured on decode
+    trim: Gain
+}
+
+/// Memory held by a [`SoundBank`], in bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BankMemoryUsage {
+    /// registered WAV data kept in memory, file assets don't count
+    pub encoded: usize,
+    /// decoded samples
+    pub decoded: usize,
+    pub loaded_assets: usize
+}
+
+/// Named sound assets, decoded on first use.
+///
+/// Decoded sounds are shared with the voices playing them,
+/// so unloading an asset never cuts off a voice which is still playing.
+#[derive(Default)]
+pub struct SoundBank {
+    entries: Vec<BankEntry>,
+    names: HashMap<String, AssetId>
+}
+impl SoundBank {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an asset under `name`, replacing the one registered under that name before
+    pub fn register(&mut self, name: &str, asset: SoundAsset) -> AssetId {
+        let entry = BankEntry {
+            name: name.to_string(),
+            asset,
+            decoded: None,
+            trim: Gain::UNITY
+        };
+        if let Some(id) = self.names.get(name) {
+            self.entries[id.0] = entry;
+            return *id;
+        }
+        let id = AssetId(self.entries.len());
+        self.entries.push(entry);
+        self.names.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn id(&self, name: &str) -> Option<AssetId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn name(&self, id: AssetId) -> Option<&str> {
+        self.entries.get(id.0).map(|entry| entry.name.as_str())
+    }
+
+    pub fn is_loaded<'a>(&self, key: impl Into<AssetKey<'a>>) -> bool {
+        self.resolve(key.into())
+            .map(|id| self.entries[id.0].decoded.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Decoded sound, decoding and normalizing it on first use
+    pub fn sound<'a>(&mut self, key: impl Into<AssetKey<'a>>) -> std::io::Result<Arc<Sound>> {
+        let id = self.resolve(key.into()).ok_or_else(unknown_asset)?;
+        let entry = &mut self.entries[id.0];
+        if let Some(sound) = &entry.decoded {
+            return Ok(sound.clone());
+        }
+        let mut sound = match &entry.asset.data {
+            AssetData::Bytes(bytes) => Sound::from_bytes_ext(bytes, entry.asset.playback_style)?,
+            AssetData::File(path) => Sound::from_bytes_ext(&std::fs::read(path)?, entry.asset.playback_style)?
+        };
+        match entry.asset.normalization {
+            Some(Normalization::AtLoad(target_lufs)) => {
+                sound.normalize(target_lufs, NORMALIZATION_CEILING_DB);
+            },
+            Some(Normalization::AtPlay(target_lufs)) => {
+                entry.trim = sound.loudness().normalization_gain(target_lufs, NORMALIZATION_CEILING_DB);
+            },
+            None => {}
+        }
+        let sound = Arc::new(sound);
+        entry.decoded = Some(sound.clone());
+        Ok(sound)
+    }
+
+    /// Decodes every asset of `group` ahead of time, e.g. during a loading screen
+    pub fn preload_group(&mut self, group: &str) -> std::io::Result<()> {
+        for i in 0..self.entries.len() {
+            if self.entries[i].asset.group == group {
+                self.sound(AssetId(i))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the decoded data of an asset, it gets decoded again on next use
+    pub fn unload<'a>(&mut self, key: impl Into<AssetKey<'a>>) {
+        if let Some(id) = self.resolve(key.into()) {
+            self.entries[id.0].decoded = None;
+        }
+    }
+
+    pub fn unload_group(&mut self, group: &str) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.asset.group == group) {
+            entry.decoded = None;
+        }
+    }
+
+    pub fn memory_usage(&self) -> BankMemoryUsage {
+        let mut usage = BankMemoryUsage::default();
+        for entry in self.entries.iter() {
+            if let AssetData::Bytes(bytes) = &entry.asset.data {
+                usage.encoded += bytes.len();
+            }
+            if let Some(sound) = &entry.decoded {
+                usage.decoded += sound.samples.len() * std::mem::size_of::<f32>();
+                usage.loaded_assets += 1;
+            }
+        }
+        usage
+    }
+
+    /// Builder playing the asset, to be customized further before passing it to [`SoundMixer::play`]
+    pub fn builder<'a>(&mut self, key: impl Into<AssetKey<'a>>) -> std::io::Result<PlaybackBuilder> {
+        let id = self.resolve(key.into()).ok_or_else(unknown_asset)?;
+        let sound = self.sound(id)?;
+        Ok(PlaybackBuilder::new()
+            .with_shared_sound(sound)
+            .with_trim(self.entries[id.0].trim)
+            .with_asset(&self.entries[id.0].name))
+    }
+
+    pub fn play<'a>(&mut self, mixer: &mut SoundMixer, key: impl Into<AssetKey<'a>>) -> std::io::Result<Option<SoundId>> {
+        Ok(mixer.play(self.builder(key)?))
+    }
+
+    fn resolve(&self, key: AssetKey) -> Option<AssetId> {
+        match key {
+            AssetKey::Name(name) => self.id(name),
+            AssetKey::Id(id) if id.0 < self.entries.len() => Some(id),
+            AssetKey::Id(_) => None
+        }
+    }
+}
+
+fn unknown_asset() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, "sound asset is not registered in the bank")
+}
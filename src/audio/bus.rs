@@ -0,0 +1,178 @@
+//! Mixer buses and side-chain ducking between them.
+//!
+//! Every voice plays through a bus, [`BusId::MASTER`] unless set with
+//! [`crate::audio::PlaybackBuilder::with_bus`]. Buses are summed into the master,
+//! whose volume is the one set with [`crate::audio::SoundMixer::set_volume_self`].
+//! Each bus can also run an EQ and a compressor, see [`crate::audio::effects`].
+use std::sync::Arc;
+use crate::audio::meter::{LevelMeter, MeterAccumulator};
+use crate::audio::effects::{EqState, CompressorState};
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct BusId(pub(crate) usize);
+impl BusId {
+    pub const MASTER: BusId = BusId(0);
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct DuckingId(pub(crate) usize);
+
+/// What makes a [`Ducking`] kick in
+#[derive(Clone, Debug, PartialEq)]
+pub enum DuckTrigger {
+    /// anything audible on the bus
+    Bus(BusId),
+    /// any audible voice started with [`crate::audio::PlaybackBuilder::with_tag`]
+    Tag(Arc<str>)
+}
+
+/// Side-chain rule lowering the `target` bus while the trigger is audible,
+/// e.g. music ducked under dialogue
+#[derive(Clone, Debug)]
+pub struct Ducking {
+    pub(crate) trigger: DuckTrigger,
+    pub(crate) target: BusId,
+    pub(crate) attenuation_db: f32,
+    pub(crate) threshold_db: f32,
+    pub(crate) attack_ms: f32,
+    pub(crate) hold_ms: f32,
+    pub(crate) release_ms: f32
+}
+impl Ducking {
+    pub fn new(trigger: DuckTrigger, target: BusId) -> Self {
+        Self {
+            trigger,
+            target,
+            attenuation_db: 9.0,
+            threshold_db: -40.0,
+            attack_ms: 50.0,
+            hold_ms: 150.0,
+            release_ms: 500.0
+        }
+    }
+    /// how far the target goes down, in dB
+    pub fn with_attenuation_db(self, attenuation_db: f32) -> Self {
+        Self {
+            attenuation_db: attenuation_db.abs(),
+            ..self
+        }
+    }
+    /// trigger peak level above which it counts as audible, in dBFS
+    pub fn with_threshold_db(self, threshold_db: f32) -> Self {
+        Self {
+            threshold_db,
+            ..self
+        }
+    }
+    pub fn with_attack_ms(self, attack_ms: f32) -> Self {
+        Self {
+            attack_ms,
+            ..self
+        }
+    }
+    /// how long the trigger may go quiet before the release starts, bridges pauses between words
+    pub fn with_hold_ms(self, hold_ms: f32) -> Self {
+        Self {
+            hold_ms,
+            ..self
+        }
+    }
+    pub fn with_release_ms(self, release_ms: f32) -> Self {
+        Self {
+            release_ms,
+            ..self
+        }
+    }
+}
+
+pub(crate) fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// one pole smoothing coefficient reaching ~63% of the way in `ms`
+pub(crate) fn smoothing_coefficient(ms: f32, sample_rate: f32) -> f32 {
+    let frames = ms * 0.001 * sample_rate;
+    if frames <= 1.0 {
+        1.0
+    } else {
+        1.0 - (-1.0 / frames).exp()
+    }
+}
+
+pub(crate) struct Bus {
+    /// linear
+    pub(crate) gain: f32,
+    /// product of all duckings targeting this bus
+    pub(crate) duck_gain: f32,
+    pub(crate) frame: [f32; 2],
+    pub(crate) peak: f32,
+    pub(crate) meter: MeterAccumulator,
+    pub(crate) eq: Option<EqState>,
+    pub(crate) compressor: Option<CompressorState>
+}
+impl Bus {
+    pub(crate) fn new(meter: LevelMeter) -> Self {
+        Self {
+            gain: 1.0,
+            duck_gain: 1.0,
+            frame: [0.0; 2],
+            peak: 0.0,
+            meter: MeterAccumulator::new(meter),
+            eq: None,
+            compressor: None
+        }
+    }
+    /// runs the bus frame through the EQ and the compressor
+    pub(crate) fn process_effects(&mut self) -> [f32; 2] {
+        let mut frame = self.frame;
+        if let Some(eq) = &mut self.eq {
+            frame = eq.process(frame);
+        }
+        if let Some(compressor) = &mut self.compressor {
+            frame = compressor.process(frame);
+        }
+        frame
+    }
+}
+
+pub(crate) struct DuckingState {
+    pub(crate) id: DuckingId,
+    pub(crate) trigger: DuckTrigger,
+    pub(crate) target: BusId,
+    /// trigger peak of the current frame
+    pub(crate) level: f32,
+    pub(crate) gain: f32,
+    attenuation: f32,
+    threshold: f32,
+    attack: f32,
+    release: f32,
+    hold_frames: usize,
+    hold: usize
+}
+impl DuckingState {
+    pub(crate) fn new(id: DuckingId, ducking: Ducking, sample_rate: f32) -> Self {
+        Self {
+            id,
+            trigger: ducking.trigger,
+            target: ducking.target,
+            level: 0.0,
+            gain: 1.0,
+            attenuation: db_to_linear(-ducking.attenuation_db),
+            threshold: db_to_linear(ducking.threshold_db),
+            attack: smoothing_coefficient(ducking.attack_ms, sample_rate),
+            release: smoothing_coefficient(ducking.release_ms, sample_rate),
+            hold_frames: (ducking.hold_ms * 0.001 * sample_rate) as usize,
+            hold: 0
+        }
+    }
+    pub(crate) fn update(&mut self) {
+        if self.level > self.threshold {
+            self.hold = self.hold_frames + 1;
+        } else if self.hold > 0 {
+            self.hold -= 1;
+        }
+        let target = if self.hold > 0 { self.attenuation } else { 1.0 };
+        let coefficient = if target < self.gain { self.attack } else { self.release };
+        self.gain += (target - self.gain) * coefficient;
+    }
+}
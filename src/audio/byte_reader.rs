@@ -0,0 +1,76 @@
+use std::io::{Error, ErrorKind};
+
+/// Bounds-checked little helper for picking apart binary asset formats
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+    pub(crate) fn seek(&mut self, pos: usize) -> std::io::Result<()> {
+        if pos > self.bytes.len() {
+            return Err(unexpected_eof());
+        }
+        self.pos = pos;
+        Ok(())
+    }
+    pub(crate) fn skip(&mut self, count: usize) -> std::io::Result<()> {
+        self.seek(self.pos + count)
+    }
+    pub(crate) fn bytes(&mut self, count: usize) -> std::io::Result<&'a [u8]> {
+        if self.pos + count > self.bytes.len() {
+            return Err(unexpected_eof());
+        }
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(slice)
+    }
+    /// like [`ByteReader::bytes`], but returns whatever is left if there are less than `count` bytes
+    pub(crate) fn bytes_truncated(&mut self, count: usize) -> &'a [u8] {
+        let count = count.min(self.bytes.len() - self.pos);
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        slice
+    }
+    pub(crate) fn u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub(crate) fn i8(&mut self) -> std::io::Result<i8> {
+        Ok(self.u8()? as i8)
+    }
+    pub(crate) fn u16_le(&mut self) -> std::io::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+    pub(crate) fn u16_be(&mut self) -> std::io::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    pub(crate) fn u32_le(&mut self) -> std::io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    pub(crate) fn u64_le(&mut self) -> std::io::Result<u64> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+    /// fixed size, zero padded text field
+    pub(crate) fn text(&mut self, count: usize) -> std::io::Result<String> {
+        let raw = self.bytes(count)?;
+        let end = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+        Ok(raw[..end].iter().map(|b| *b as char).collect::<String>().trim_end().to_string())
+    }
+}
+
+fn unexpected_eof() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "unexpected end of data")
+}
+
+pub(crate) fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
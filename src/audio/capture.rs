@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom};
+use std::sync::mpsc::Receiver;
+use crate::audio::mixer::{Sound, PlaybackStyle, AudioClock};
+use crate::audio::wav;
+
+enum CaptureTarget {
+    Memory(Vec<f32>),
+    File { writer: BufWriter<File>, sample_count: usize }
+}
+
+/// Recording of the mixer's master output, started with [`crate::audio::SoundMixer::capture_to_memory`]
+/// or [`crate::audio::SoundMixer::capture_to_file`].
+///
+/// The audio thread hands over a chunk per delivered device buffer.
+/// [`MixerCapture::poll`] should be called every frame to move them into the target,
+/// [`MixerCapture::finish`] ends the recording. Dropping the capture stops it too.
+pub struct MixerCapture {
+    receiver: Receiver<Vec<f32>>,
+    clock: AudioClock,
+    target: CaptureTarget
+}
+impl MixerCapture {
+    pub(crate) fn to_memory(receiver: Receiver<Vec<f32>>, clock: AudioClock) -> Self {
+        Self {
+            receiver,
+            clock,
+            target: CaptureTarget::Memory(Vec::new())
+        }
+    }
+
+    pub(crate) fn to_file(file: File, receiver: Receiver<Vec<f32>>, clock: AudioClock) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(file);
+        // the sizes get patched in by `finish`
+        wav::write_header(&mut writer, clock.sample_rate() as u32, 2, 0)?;
+        Ok(Self {
+            receiver,
+            clock,
+            target: CaptureTarget::File { writer, sample_count: 0 }
+        })
+    }
+
+    /// Number of stereo frames recorded so far
+    pub fn frames(&self) -> usize {
+        match &self.target {
+            CaptureTarget::Memory(samples) => samples.len() / 2,
+            CaptureTarget::File { sample_count, .. } => sample_count / 2
+        }
+    }
+
+    /// Moves everything the audio thread has recorded so far into the target
+    pub fn poll(&mut self) -> std::io::Result<()> {
+        for chunk in self.receiver.try_iter() {
+            match &mut self.target {
+                CaptureTarget::Memory(samples) => samples.extend_from_slice(&chunk),
+                CaptureTarget::File { writer, sample_count } => {
+                    wav::write_samples(writer, &chunk)?;
+                    *sample_count += chunk.len();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops recording. Returns the recorded sound for in-memory captures,
+    /// `None` for file captures once the file is finalized.
+    pub fn finish(mut self) -> std::io::Result<Option<Sound>> {
+        self.poll()?;
+        let sample_rate = self.clock.sample_rate();
+        match self.target {
+            CaptureTarget::Memory(samples) => Ok(Some(Sound {
+                sample_rate,
+                channels: 2,
+                samples,
+                playback_style: PlaybackStyle::Once
+            })),
+            CaptureTarget::File { mut writer, sample_count } => {
+                writer.seek(SeekFrom::Start(0))?;
+                wav::write_header(&mut writer, sample_rate as u32, 2, sample_count)?;
+                writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use crate::audio::mixer::{Sound, PlaybackBuilder, Volume};
+use crate::audio::source::{SampleSource, PitchedSource};
+use crate::audio::rng::XorShift32;
+use crate::audio::volume::VolumeError;
+
+/// How a [`SoundContainer`] picks the variant to play
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PickMode {
+    /// any variant but the one played last
+    Random,
+    /// variants in the order they were added
+    Sequential,
+    /// every variant once in random order, then reshuffled
+    Shuffled
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// error produced when a pitch range of a [`SoundContainer`] is invalid
+pub enum PitchError {
+    /// the value is NaN or infinite
+    NotFinite,
+    /// a playback speed of zero or below
+    OutOfRange(f32)
+}
+impl Display for PitchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PitchError::NotFinite => write!(f, "pitch is not a finite number"),
+            PitchError::OutOfRange(value) => write!(f, "pitch {} is not above 0.0", value)
+        }
+    }
+}
+impl std::error::Error for PitchError {}
+
+/// Group of variants of one sound, e.g. footsteps or unit acknowledgements.
+///
+/// Each play picks a variant and randomizes its volume and pitch within the configured ranges.
+/// Pass `&mut container` to [`crate::audio::SoundMixer::play`] in place of a [`PlaybackBuilder`].
+pub struct SoundContainer {
+    sounds: Vec<Arc<Sound>>,
+    pick_mode: PickMode,
+    volume_range: (f32, f32),
+    pitch_range: (f32, f32),
+    order: Vec<usize>,
+    cursor: usize,
+    last: Option<usize>,
+    rng: XorShift32
+}
+impl SoundContainer {
+    pub fn new(sounds: Vec<Sound>) -> Self {
+        Self::shared(sounds.into_iter().map(Arc::new).collect())
+    }
+    /// Container over sounds shared with e.g. a [`crate::audio::SoundBank`]
+    pub fn shared(sounds: Vec<Arc<Sound>>) -> Self {
+        Self {
+            order: (0..sounds.len()).collect(),
+            sounds,
+            pick_mode: PickMode::Random,
+            volume_range: (1.0, 1.0),
+            pitch_range: (1.0, 1.0),
+            cursor: 0,
+            last: None,
+            rng: XorShift32::from_time()
+        }
+    }
+    /// the new mode starts over, e.g. [`PickMode::Shuffled`] reshuffles on the next pick
+    pub fn with_pick_mode(self, pick_mode: PickMode) -> Self {
+        Self {
+            pick_mode,
+            cursor: 0,
+            ..self
+        }
+    }
+    /// volume of each play is picked uniformly from `min..=max`, both bounds must be valid volumes
+    pub fn with_volume_range(self, min: f32, max: f32) -> Result<Self, VolumeError> {
+        let min = Volume(min).validate()?.0;
+        let max = Volume(max).validate()?.0;
+        Ok(Self {
+            volume_range: (min.min(max), min.max(max)),
+            ..self
+        })
+    }
+    /// playback speed of each play is picked uniformly from `min..=max`, 1.0 is the original pitch
+    pub fn with_pitch_range(self, min: f32, max: f32) -> Result<Self, PitchError> {
+        for pitch in [min, max] {
+            if !pitch.is_finite() {
+                return Err(PitchError::NotFinite);
+            }
+            if pitch <= 0.0 {
+                return Err(PitchError::OutOfRange(pitch));
+            }
+        }
+        Ok(Self {
+            pitch_range: (min.min(max), min.max(max)),
+            ..self
+        })
+    }
+    /// makes the picks reproducible
+    pub fn with_seed(self, seed: u32) -> Self {
+        Self {
+            rng: XorShift32::new(seed),
+            ..self
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.sounds.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.sounds.is_empty()
+    }
+
+    /// Picks the next variant, `None` for an empty container
+    pub fn pick(&mut self) -> Option<usize> {
+        let count = self.sounds.len();
+        if count == 0 {
+            return None;
+        }
+        let index = match self.pick_mode {
+            PickMode::Random => match self.last {
+                Some(last) if count > 1 => (last + 1 + self.rng.next_below(count - 1)) % count,
+                _ => self.rng.next_below(count)
+            },
+            PickMode::Sequential => {
+                let index = self.cursor % count;
+                self.cursor = index + 1;
+                index
+            }
+            PickMode::Shuffled => {
+                if self.cursor == 0 || self.cursor >= count {
+                    self.reshuffle();
+                }
+                let index = self.order[self.cursor];
+                self.cursor += 1;
+                index
+            }
+        };
+        self.last = Some(index);
+        Some(index)
+    }
+
+    /// Picks a variant and returns a builder playing it, `None` for an empty container
+    pub fn builder(&mut self) -> Option<PlaybackBuilder> {
+        let index = self.pick()?;
+        let volume = self.random_in(self.volume_range);
+        let pitch = self.random_in(self.pitch_range);
+        let source = SampleSource::shared(self.sounds[index].clone());
+        let builder = if pitch == 1.0 {
+            PlaybackBuilder::new().with_source(source)
+        } else {
+            PlaybackBuilder::new().with_source(PitchedSource::new(source, pitch))
+        };
+        Some(builder.with_volume(Volume(volume)))
+    }
+
+    fn reshuffle(&mut self) {
+        self.order = (0..self.sounds.len()).collect();
+        self.rng.shuffle(&mut self.order);
+        // don't repeat the last variant across the reshuffle
+        if self.order.len() > 1 && Some(self.order[0]) == self.last {
+            self.order.swap(0, 1);
+        }
+        self.cursor = 0;
+    }
+
+    fn random_in(&mut self, (min, max): (f32, f32)) -> f32 {
+        if min == max {
+            min
+        } else {
+            min + (max - min) * self.rng.next_f32()
+        }
+    }
+}
+
+impl From<&mut SoundContainer> for PlaybackBuilder {
+    /// an empty container turns into a builder without a source, which the mixer ignores
+    fn from(container: &mut SoundContainer) -> Self {
+        container.builder().unwrap_or_else(PlaybackBuilder::new)
+    }
+}
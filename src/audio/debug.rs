@@ -0,0 +1,50 @@
+//! Introspection for developer consoles, see [`crate::audio::SoundMixer::debug_info`].
+use crate::audio::mixer::{SoundId, PlaybackStyle};
+use crate::audio::bus::BusId;
+
+/// State of one voice as the audio thread sees it
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoiceDebugInfo {
+    pub id: SoundId,
+    /// [`crate::audio::SoundBank`] asset name, if played from a bank
+    pub asset: Option<String>,
+    pub tag: Option<String>,
+    /// frames into the source, if it can tell
+    pub position: Option<usize>,
+    /// the voice's own linear gain
+    pub gain: f32,
+    /// gain after the bus, ducking and master volume, zero means it can't be heard
+    pub effective_gain: f32,
+    pub bus: BusId,
+    pub bus_name: String,
+    /// `None` for sources other than decoded samples, e.g. tracker modules
+    pub playback_style: Option<PlaybackStyle>,
+    pub looping: bool,
+    pub paused: bool,
+    /// a streamed voice waiting for more content
+    pub starving: bool,
+    /// mixer frame the voice waits for, see [`crate::audio::PlaybackBuilder::with_start_frame`]
+    pub scheduled_frame: Option<u64>
+}
+
+/// Running totals since the mixer was created
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MixerStats {
+    /// messages the audio thread has handled
+    pub messages_processed: u64,
+    /// voices removed because their source finished
+    pub voices_culled: u64,
+    /// voices removed by [`crate::audio::SoundMixer::stop`]
+    pub voices_stopped: u64,
+    /// [`crate::audio::SoundMixer::play`] calls which had no playable source or an invalid volume
+    pub plays_rejected: u64,
+    /// device buffers the render-ahead queue could not fill, see [`crate::audio::OutputInfo`]
+    pub underruns: u64
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixerDebugInfo {
+    /// playing and scheduled voices, ordered by id
+    pub voices: Vec<VoiceDebugInfo>,
+    pub stats: MixerStats
+}
@@ -0,0 +1,167 @@
+//! Offline editing of decoded sounds, e.g. preparing assets at load time.
+//!
+//! Every operation returns a new [`Sound`] and keeps the playback style of the original.
+use std::f64::consts::PI;
+use crate::audio::mixer::Sound;
+
+/// zero crossings of the resampling kernel on each side
+const SINC_HALF_WIDTH: usize = 16;
+
+impl Sound {
+    pub fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    fn with_samples(&self, sample_rate: f32, channels: u16, samples: Vec<f32>) -> Sound {
+        Sound {
+            sample_rate,
+            channels,
+            samples,
+            playback_style: self.playback_style
+        }
+    }
+
+    /// Duplicates a mono sound into both channels, stereo sounds are copied as they are
+    pub fn to_stereo(&self) -> Sound {
+        if self.channels != 1 {
+            return self.clone();
+        }
+        let samples = self.samples.iter().flat_map(|sample| [*sample, *sample]).collect();
+        self.with_samples(self.sample_rate, 2, samples)
+    }
+
+    /// Averages the channels of a stereo sound, mono sounds are copied as they are
+    pub fn to_mono(&self) -> Sound {
+        if self.channels != 2 {
+            return self.clone();
+        }
+        let samples = self.samples.chunks_exact(2).map(|frame| (frame[0] + frame[1]) * 0.5).collect();
+        self.with_samples(self.sample_rate, 1, samples)
+    }
+
+    /// Sound with the given channel count, 1 or 2
+    pub fn with_channels(&self, channels: u16) -> Sound {
+        match channels {
+            1 => self.to_mono(),
+            _ => self.to_stereo()
+        }
+    }
+
+    /// Cuts off leading and trailing frames whose every channel stays below `threshold_db`
+    pub fn trim_silence(&self, threshold_db: f32) -> Sound {
+        let channels = self.channels.max(1) as usize;
+        let threshold = 10f32.powf(threshold_db / 20.0);
+        let is_audible = |frame: &[f32]| frame.iter().any(|sample| sample.abs() >= threshold);
+        let frames: Vec<&[f32]> = self.samples.chunks_exact(channels).collect();
+        let start = frames.iter().position(|frame| is_audible(frame));
+        let end = frames.iter().rposition(|frame| is_audible(frame));
+        let samples = match (start, end) {
+            (Some(start), Some(end)) => self.samples[start * channels..(end + 1) * channels].to_vec(),
+            _ => Vec::new()
+        };
+        self.with_samples(self.sample_rate, self.channels, samples)
+    }
+
+    /// Converts the sound to another sample rate with a windowed sinc filter,
+    /// so that it plays without the mixer's own rate correction.
+    /// Both rates must be finite and above zero.
+    pub fn resampled(&self, sample_rate: f32) -> std::io::Result<Sound> {
+        for rate in [sample_rate, self.sample_rate] {
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("cannot resample at {} Hz", rate)
+                ));
+            }
+        }
+        if sample_rate == self.sample_rate {
+            return Ok(self.clone());
+        }
+        let channels = self.channels.max(1) as usize;
+        let frames = self.frame_count();
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        // when downsampling the cutoff moves down to the new nyquist frequency
+        let cutoff = ratio.min(1.0);
+        let half_width = (SINC_HALF_WIDTH as f64 / cutoff).ceil() as isize;
+        let new_frames = (frames as f64 * ratio).round() as usize;
+
+        let mut samples = vec![0.0f32; new_frames * channels];
+        for (frame, output) in samples.chunks_exact_mut(channels).enumerate() {
+            let position = frame as f64 / ratio;
+            let center = position.floor() as isize;
+            let mut weight_sum = 0.0;
+            let mut sums = [0.0f64; 2];
+            for source_frame in (center - half_width + 1)..=(center + half_width) {
+                if source_frame < 0 || source_frame as usize >= frames {
+                    continue;
+                }
+                let distance = (position - source_frame as f64) * cutoff;
+                let weight = sinc(distance) * blackman(distance / SINC_HALF_WIDTH as f64);
+                weight_sum += weight;
+                let offset = source_frame as usize * channels;
+                for (channel, sum) in sums.iter_mut().take(channels).enumerate() {
+                    *sum += self.samples[offset + channel] as f64 * weight;
+                }
+            }
+            // dividing by the sum of weights keeps the level steady where the kernel is cut short
+            let normalize = if weight_sum.abs() > f64::EPSILON { 1.0 / weight_sum } else { 0.0 };
+            for (sample, sum) in output.iter_mut().zip(sums.iter()) {
+                *sample = (sum * normalize) as f32;
+            }
+        }
+        Ok(self.with_samples(sample_rate, self.channels, samples))
+    }
+
+    /// Splits the sound at the given frames, e.g. the markers of a sound sheet.
+    /// Markers outside of the sound are ignored, empty pieces are left out.
+    pub fn split(&self, markers: &[usize]) -> Vec<Sound> {
+        let channels = self.channels.max(1) as usize;
+        let frames = self.frame_count();
+        let mut bounds: Vec<usize> = markers.iter()
+            .cloned()
+            .filter(|marker| *marker > 0 && *marker < frames)
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        bounds.insert(0, 0);
+        bounds.push(frames);
+        bounds.windows(2)
+            .filter(|range| range[1] > range[0])
+            .map(|range| {
+                let samples = self.samples[range[0] * channels..range[1] * channels].to_vec();
+                self.with_samples(self.sample_rate, self.channels, samples)
+            })
+            .collect()
+    }
+
+    /// Joins clips one after another. The result takes the sample rate, channel count and
+    /// playback style of the first clip, the others are converted to match.
+    /// Fails if there are no clips or one can't be resampled, see [`Sound::resampled`].
+    pub fn concatenate(clips: &[Sound]) -> std::io::Result<Sound> {
+        let (first, rest) = clips.split_first()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no clips to concatenate"))?;
+        let mut result = first.clone();
+        for clip in rest {
+            let clip = clip.with_channels(first.channels).resampled(first.sample_rate)?;
+            result.samples.extend_from_slice(&clip.samples);
+        }
+        Ok(result)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// blackman window over `-1.0..=1.0`
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let phase = PI * (x + 1.0);
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
@@ -0,0 +1,252 @@
+//! Bus effects: a parametric EQ followed by a compressor, on any bus including the master.
+//!
+//! Both run before the bus volume, so riding a fader doesn't change how hard the compressor works.
+use crate::audio::bus::{db_to_linear, smoothing_coefficient};
+use crate::audio::filter::Biquad;
+use crate::audio::meter::linear_to_db;
+use crate::audio::volume::MAX_GAIN_DB;
+use std::fmt::{Display, Formatter};
+
+/// error produced when an [`EqBand`] or [`Compressor`] parameter is out of range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectError {
+    /// a parameter is NaN or infinite
+    NotFinite,
+    /// a frequency of zero or below
+    FrequencyOutOfRange(f32),
+    /// a q of zero or below
+    QOutOfRange(f32),
+    /// a gain outside of -[`MAX_GAIN_DB`]..=+[`MAX_GAIN_DB`]
+    GainOutOfRange(f32),
+    /// a threshold above 0 dBFS
+    ThresholdOutOfRange(f32),
+    /// a ratio below 1.0
+    RatioOutOfRange(f32),
+    /// a negative attack or release time
+    TimeOutOfRange(f32)
+}
+impl Display for EffectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EffectError::NotFinite => write!(f, "effect parameter is not a finite number"),
+            EffectError::FrequencyOutOfRange(value) => write!(f, "frequency {}Hz is not above 0", value),
+            EffectError::QOutOfRange(value) => write!(f, "q {} is not above 0.0", value),
+            EffectError::GainOutOfRange(value) => write!(f, "gain {}dB is outside of -{1}..=+{1}dB", value, MAX_GAIN_DB),
+            EffectError::ThresholdOutOfRange(value) => write!(f, "threshold {}dB is above 0dBFS", value),
+            EffectError::RatioOutOfRange(value) => write!(f, "ratio {} is below 1.0", value),
+            EffectError::TimeOutOfRange(value) => write!(f, "time {}ms is negative", value)
+        }
+    }
+}
+impl std::error::Error for EffectError {}
+
+fn finite(values: &[f32]) -> Result<(), EffectError> {
+    if values.iter().all(|value| value.is_finite()) {
+        Ok(())
+    } else {
+        Err(EffectError::NotFinite)
+    }
+}
+
+fn validate_gain_db(gain_db: f32) -> Result<(), EffectError> {
+    if gain_db.abs() > MAX_GAIN_DB {
+        return Err(EffectError::GainOutOfRange(gain_db));
+    }
+    Ok(())
+}
+
+/// Dynamic range compressor, see [`crate::audio::SoundMixer::set_bus_compressor`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compressor {
+    pub(crate) threshold_db: f32,
+    pub(crate) ratio: f32,
+    pub(crate) attack_ms: f32,
+    pub(crate) release_ms: f32,
+    pub(crate) makeup_db: f32
+}
+impl Default for Compressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Compressor {
+    pub fn new() -> Self {
+        Self {
+            threshold_db: -18.0,
+            ratio: 4.0,
+            attack_ms: 10.0,
+            release_ms: 200.0,
+            makeup_db: 0.0
+        }
+    }
+    /// peak level above which the compressor starts working, in dBFS
+    pub fn with_threshold_db(self, threshold_db: f32) -> Self {
+        Self {
+            threshold_db,
+            ..self
+        }
+    }
+    /// how many dB over the threshold it takes for the output to rise by one dB, at least 1.0
+    pub fn with_ratio(self, ratio: f32) -> Self {
+        Self {
+            ratio,
+            ..self
+        }
+    }
+    pub fn with_attack_ms(self, attack_ms: f32) -> Self {
+        Self {
+            attack_ms,
+            ..self
+        }
+    }
+    pub fn with_release_ms(self, release_ms: f32) -> Self {
+        Self {
+            release_ms,
+            ..self
+        }
+    }
+    /// gain added after compression to win back the level it took away, in dB
+    pub fn with_makeup_db(self, makeup_db: f32) -> Self {
+        Self {
+            makeup_db,
+            ..self
+        }
+    }
+    pub fn validate(self) -> Result<Self, EffectError> {
+        finite(&[self.threshold_db, self.ratio, self.attack_ms, self.release_ms, self.makeup_db])?;
+        if self.threshold_db > 0.0 {
+            return Err(EffectError::ThresholdOutOfRange(self.threshold_db));
+        }
+        if self.ratio < 1.0 {
+            return Err(EffectError::RatioOutOfRange(self.ratio));
+        }
+        for time in [self.attack_ms, self.release_ms] {
+            if time < 0.0 {
+                return Err(EffectError::TimeOutOfRange(time));
+            }
+        }
+        validate_gain_db(self.makeup_db)?;
+        Ok(self)
+    }
+}
+
+/// Filter shape of an [`EqBand`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EqBandKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+    /// removes everything below the frequency, the gain is ignored
+    HighPass,
+    /// removes everything above the frequency, the gain is ignored
+    LowPass
+}
+
+/// One band of a parametric EQ, see [`crate::audio::SoundMixer::set_bus_eq`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqBand {
+    pub kind: EqBandKind,
+    /// center or corner frequency in Hz
+    pub frequency: f32,
+    /// width of the band, 0.707 is a gentle default
+    pub q: f32,
+    pub gain_db: f32
+}
+impl EqBand {
+    pub fn low_shelf(frequency: f32, gain_db: f32) -> Self {
+        Self { kind: EqBandKind::LowShelf, frequency, q: 0.707, gain_db }
+    }
+    pub fn peaking(frequency: f32, q: f32, gain_db: f32) -> Self {
+        Self { kind: EqBandKind::Peaking, frequency, q, gain_db }
+    }
+    pub fn high_shelf(frequency: f32, gain_db: f32) -> Self {
+        Self { kind: EqBandKind::HighShelf, frequency, q: 0.707, gain_db }
+    }
+    pub fn high_pass(frequency: f32) -> Self {
+        Self { kind: EqBandKind::HighPass, frequency, q: 0.707, gain_db: 0.0 }
+    }
+    pub fn low_pass(frequency: f32) -> Self {
+        Self { kind: EqBandKind::LowPass, frequency, q: 0.707, gain_db: 0.0 }
+    }
+
+    pub fn validate(self) -> Result<Self, EffectError> {
+        finite(&[self.frequency, self.q, self.gain_db])?;
+        if self.frequency <= 0.0 {
+            return Err(EffectError::FrequencyOutOfRange(self.frequency));
+        }
+        if self.q <= 0.0 {
+            return Err(EffectError::QOutOfRange(self.q));
+        }
+        validate_gain_db(self.gain_db)?;
+        Ok(self)
+    }
+
+    fn filter(&self, sample_rate: f32) -> Biquad {
+        let q = self.q.max(0.01);
+        // the output rate is only known on the audio thread, keep the band below nyquist there
+        let frequency = self.frequency.min(sample_rate * 0.49);
+        match self.kind {
+            EqBandKind::LowShelf => Biquad::low_shelf(frequency, q, self.gain_db, sample_rate),
+            EqBandKind::Peaking => Biquad::peaking(frequency, q, self.gain_db, sample_rate),
+            EqBandKind::HighShelf => Biquad::high_shelf(frequency, q, self.gain_db, sample_rate),
+            EqBandKind::HighPass => Biquad::high_pass(frequency, q, sample_rate),
+            EqBandKind::LowPass => Biquad::low_pass(frequency, q, sample_rate)
+        }
+    }
+}
+
+/// audio thread side of an EQ, one filter per band and channel
+pub(crate) struct EqState {
+    filters: Vec<[Biquad; 2]>
+}
+impl EqState {
+    pub(crate) fn new(bands: &[EqBand], sample_rate: f32) -> Self {
+        Self {
+            filters: bands.iter()
+                .map(|band| {
+                    let filter = band.filter(sample_rate);
+                    [filter, filter]
+                })
+                .collect()
+        }
+    }
+    pub(crate) fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let mut frame = frame;
+        for filters in self.filters.iter_mut() {
+            frame[0] = filters[0].process(frame[0]);
+            frame[1] = filters[1].process(frame[1]);
+        }
+        frame
+    }
+}
+
+/// audio thread side of a [`Compressor`], both channels share one gain so the image doesn't shift
+pub(crate) struct CompressorState {
+    threshold_db: f32,
+    slope: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    /// smoothed gain reduction in dB
+    reduction_db: f32
+}
+impl CompressorState {
+    pub(crate) fn new(compressor: Compressor, sample_rate: f32) -> Self {
+        Self {
+            threshold_db: compressor.threshold_db,
+            slope: 1.0 - 1.0 / compressor.ratio.max(1.0),
+            attack: smoothing_coefficient(compressor.attack_ms, sample_rate),
+            release: smoothing_coefficient(compressor.release_ms, sample_rate),
+            makeup: db_to_linear(compressor.makeup_db),
+            reduction_db: 0.0
+        }
+    }
+    pub(crate) fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let level_db = linear_to_db(frame[0].abs().max(frame[1].abs()));
+        let target = (level_db - self.threshold_db).max(0.0) * self.slope;
+        let coefficient = if target > self.reduction_db { self.attack } else { self.release };
+        self.reduction_db += (target - self.reduction_db) * coefficient;
+        let gain = db_to_linear(-self.reduction_db) * self.makeup;
+        [frame[0] * gain, frame[1] * gain]
+    }
+}
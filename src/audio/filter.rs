@@ -0,0 +1,91 @@
+use std::f32::consts::PI;
+
+/// Second order IIR section in direct form I, coefficients normalized by `a0`
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32
+}
+impl Biquad {
+    pub(crate) fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0
+        }
+    }
+
+    fn omega(frequency: f32, sample_rate: f32) -> (f32, f32) {
+        let w0 = 2.0 * PI * (frequency / sample_rate).clamp(0.0001, 0.4999);
+        (w0.cos(), w0.sin())
+    }
+
+    pub(crate) fn high_pass(frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let (cos, sin) = Self::omega(frequency, sample_rate);
+        let alpha = sin / (2.0 * q);
+        Self::new((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    pub(crate) fn low_pass(frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let (cos, sin) = Self::omega(frequency, sample_rate);
+        let alpha = sin / (2.0 * q);
+        Self::new((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    pub(crate) fn peaking(frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let (cos, sin) = Self::omega(frequency, sample_rate);
+        let alpha = sin / (2.0 * q);
+        let a = 10f32.powf(gain_db / 40.0);
+        Self::new(1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a)
+    }
+
+    pub(crate) fn low_shelf(frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let (cos, sin) = Self::omega(frequency, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+        let beta = 2.0 * a.sqrt() * sin / (2.0 * q);
+        Self::new(
+            a * ((a + 1.0) - (a - 1.0) * cos + beta),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            (a + 1.0) + (a - 1.0) * cos + beta,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - beta
+        )
+    }
+
+    pub(crate) fn high_shelf(frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let (cos, sin) = Self::omega(frequency, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+        let beta = 2.0 * a.sqrt() * sin / (2.0 * q);
+        Self::new(
+            a * ((a + 1.0) + (a - 1.0) * cos + beta),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            (a + 1.0) - (a - 1.0) * cos + beta,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - beta
+        )
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
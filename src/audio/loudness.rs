@@ -0,0 +1,146 @@
+//! Loudness measurement after ITU-R BS.1770: K-weighted, gated integrated loudness in LUFS.
+use crate::audio::mixer::Sound;
+use crate::audio::filter::Biquad;
+use crate::audio::volume::{Gain, MAX_GAIN_DB};
+
+/// peak ceiling used when normalizing assets, leaves a little headroom for resampling and filters
+pub const NORMALIZATION_CEILING_DB: f32 = -1.0;
+
+const ABSOLUTE_GATE_LUFS: f32 = -70.0;
+const RELATIVE_GATE_LU: f32 = -10.0;
+
+/// Loudness of a [`Sound`], see [`Sound::loudness`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    /// gated integrated loudness, `f32::NEG_INFINITY` for silence
+    pub integrated_lufs: f32,
+    /// highest absolute sample value, linear
+    pub sample_peak: f32
+}
+impl Loudness {
+    pub fn sample_peak_db(&self) -> f32 {
+        20.0 * self.sample_peak.log10()
+    }
+    /// Gain bringing the sound to `target_lufs` without pushing its peak above `ceiling_db`.
+    /// Silence and immeasurably quiet sounds are left as they are.
+    pub fn normalization_gain(&self, target_lufs: f32, ceiling_db: f32) -> Gain {
+        if !self.integrated_lufs.is_finite() || self.sample_peak <= 0.0 {
+            return Gain::UNITY;
+        }
+        let gain_db = (target_lufs - self.integrated_lufs)
+            .min(ceiling_db - self.sample_peak_db())
+            .min(MAX_GAIN_DB);
+        Gain(10f32.powf(gain_db / 20.0))
+    }
+}
+
+/// How a [`crate::audio::SoundBank`] asset is brought to a common loudness
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    /// scales the decoded samples to the target in LUFS once, when the asset is loaded
+    AtLoad(f32),
+    /// keeps the samples as they are and plays the asset with a gain reaching the target in LUFS
+    AtPlay(f32)
+}
+
+/// K-weighting: head related high shelf followed by a high pass.
+/// Designed for any sample rate so that 48kHz yields the coefficients given in BS.1770.
+fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+    let k = (std::f64::consts::PI * 1_681.974_450_955_533 / sample_rate).tan();
+    let q = 0.707_175_236_955_419_6;
+    let vh = 10f64.powf(3.999_843_853_973_347 / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let shelf = Biquad::new(
+        (vh + vb * k / q + k * k) as f32,
+        (2.0 * (k * k - vh)) as f32,
+        (vh - vb * k / q + k * k) as f32,
+        (1.0 + k / q + k * k) as f32,
+        (2.0 * (k * k - 1.0)) as f32,
+        (1.0 - k / q + k * k) as f32
+    );
+
+    let k = (std::f64::consts::PI * 38.135_470_876_024_44 / sample_rate).tan();
+    let q = 0.500_327_037_323_877_3;
+    let a0 = 1.0 + k / q + k * k;
+    // the numerator is left unnormalized, as in the reference coefficients
+    let high_pass = Biquad::new(
+        1.0,
+        -2.0,
+        1.0,
+        1.0,
+        (2.0 * (k * k - 1.0) / a0) as f32,
+        ((1.0 - k / q + k * k) / a0) as f32
+    );
+    (shelf, high_pass)
+}
+
+fn loudness_of(mean_square: f32) -> f32 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+impl Sound {
+    /// Measures integrated loudness and sample peak.
+    ///
+    /// Gating works on 400ms blocks. A sound shorter than that, e.g. a click or a UI blip,
+    /// is measured as one block over its whole length, so only the absolute gate applies to it.
+    pub fn loudness(&self) -> Loudness {
+        let channels = self.channels.max(1) as usize;
+        let sample_peak = self.samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+        let mut filters: Vec<(Biquad, Biquad)> = (0..channels)
+            .map(|_| k_weighting(self.sample_rate as f64))
+            .collect();
+        let frames = self.samples.len() / channels;
+        let mut weighted = vec![0.0f32; frames];
+        for (frame, value) in self.samples.chunks_exact(channels).zip(weighted.iter_mut()) {
+            // front channels are weighted 1.0, so their squares simply add up
+            *value = frame.iter()
+                .zip(filters.iter_mut())
+                .map(|(sample, (shelf, high_pass))| {
+                    let filtered = high_pass.process(shelf.process(*sample));
+                    filtered * filtered
+                })
+                .sum();
+        }
+
+        // 400ms blocks with 75% overlap, a sound shorter than one block is one block
+        let block = ((self.sample_rate * 0.4) as usize).max(1).min(frames.max(1));
+        let step = (block / 4).max(1);
+        let mut blocks = Vec::new();
+        let mut start = 0;
+        while start + block <= frames {
+            let sum: f64 = weighted[start..start + block].iter().map(|v| *v as f64).sum();
+            blocks.push((sum / block as f64) as f32);
+            start += step;
+        }
+
+        let gated_mean = |threshold: f32| {
+            let gated: Vec<f32> = blocks.iter()
+                .cloned()
+                .filter(|mean_square| loudness_of(*mean_square) > threshold)
+                .collect();
+            if gated.is_empty() {
+                None
+            } else {
+                Some(gated.iter().map(|v| *v as f64).sum::<f64>() as f32 / gated.len() as f32)
+            }
+        };
+        let integrated_lufs = gated_mean(ABSOLUTE_GATE_LUFS)
+            // blocks have to pass both gates, quiet material can put the relative one below the absolute one
+            .and_then(|mean| gated_mean((loudness_of(mean) + RELATIVE_GATE_LU).max(ABSOLUTE_GATE_LUFS)))
+            .map(loudness_of)
+            .unwrap_or(f32::NEG_INFINITY);
+
+        Loudness { integrated_lufs, sample_peak }
+    }
+
+    /// Scales the samples to `target_lufs`, keeping the peak at or below `ceiling_db`.
+    /// Returns the gain that was applied.
+    pub fn normalize(&mut self, target_lufs: f32, ceiling_db: f32) -> Gain {
+        let gain = self.loudness().normalization_gain(target_lufs, ceiling_db);
+        for sample in self.samples.iter_mut() {
+            *sample *= gain.0;
+        }
+        gain
+    }
+}
@@ -0,0 +1,277 @@
+//! Level meters and spectrum analysis published by the audio thread.
+//!
+//! Handles are cheap to clone and read lock-free from the game thread,
+//! their values can be drawn with [`crate::image_rendering::bar_graph::BarGraph`].
+use std::sync::Arc;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::f32::consts::PI;
+
+/// lowest level meters and the analyser report, in dB
+pub const SILENCE_DB: f32 = -96.0;
+
+pub(crate) fn linear_to_db(value: f32) -> f32 {
+    if value <= 0.0 {
+        SILENCE_DB
+    } else {
+        (20.0 * value.log10()).max(SILENCE_DB)
+    }
+}
+
+/// maps a dB value to `0.0..=1.0` with `floor_db` at zero, handy for drawing.
+/// `None` unless `floor_db` is below 0 dB.
+pub fn normalize_db(db: f32, floor_db: f32) -> Option<f32> {
+    if floor_db.is_nan() || floor_db >= 0.0 {
+        return None;
+    }
+    Some(((db - floor_db) / -floor_db).clamp(0.0, 1.0))
+}
+
+#[derive(Default)]
+struct MeterShared {
+    peak: [AtomicU32; 2],
+    rms: [AtomicU32; 2]
+}
+
+/// Stereo peak and RMS level of a voice, a bus or the master, updated about 20 times a second.
+///
+/// Voices get one with [`crate::audio::PlaybackBuilder::with_meter`],
+/// buses with [`crate::audio::SoundMixer::bus_meter`].
+#[derive(Clone, Default)]
+pub struct LevelMeter(Arc<MeterShared>);
+impl LevelMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// linear peak of the left and right channel
+    pub fn peak(&self) -> [f32; 2] {
+        [load(&self.0.peak[0]), load(&self.0.peak[1])]
+    }
+    /// linear RMS of the left and right channel
+    pub fn rms(&self) -> [f32; 2] {
+        [load(&self.0.rms[0]), load(&self.0.rms[1])]
+    }
+    pub fn peak_db(&self) -> [f32; 2] {
+        let peak = self.peak();
+        [linear_to_db(peak[0]), linear_to_db(peak[1])]
+    }
+    pub fn rms_db(&self) -> [f32; 2] {
+        let rms = self.rms();
+        [linear_to_db(rms[0]), linear_to_db(rms[1])]
+    }
+    fn publish(&self, peak: [f32; 2], rms: [f32; 2]) {
+        for i in 0..2 {
+            self.0.peak[i].store(peak[i].to_bits(), Ordering::Relaxed);
+            self.0.rms[i].store(rms[i].to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+fn load(value: &AtomicU32) -> f32 {
+    f32::from_bits(value.load(Ordering::Relaxed))
+}
+
+/// audio thread side of a [`LevelMeter`]
+pub(crate) struct MeterAccumulator {
+    meter: LevelMeter,
+    peak: [f32; 2],
+    sum_of_squares: [f32; 2],
+    frames: usize
+}
+impl MeterAccumulator {
+    pub(crate) fn new(meter: LevelMeter) -> Self {
+        Self {
+            meter,
+            peak: [0.0; 2],
+            sum_of_squares: [0.0; 2],
+            frames: 0
+        }
+    }
+    pub(crate) fn add(&mut self, frame: [f32; 2], window: usize) {
+        for (i, value) in frame.iter().enumerate() {
+            self.peak[i] = self.peak[i].max(value.abs());
+            self.sum_of_squares[i] += value * value;
+        }
+        self.frames += 1;
+        if self.frames >= window {
+            let frames = self.frames as f32;
+            let rms = [
+                (self.sum_of_squares[0] / frames).sqrt(),
+                (self.sum_of_squares[1] / frames).sqrt()
+            ];
+            self.meter.publish(self.peak, rms);
+            self.peak = [0.0; 2];
+            self.sum_of_squares = [0.0; 2];
+            self.frames = 0;
+        }
+    }
+}
+
+struct SpectrumShared {
+    /// odd while the audio thread writes the bins
+    sequence: AtomicU64,
+    /// magnitude of each bin in dB, `f32` bits
+    bins: Vec<AtomicU32>,
+    sample_rate: AtomicU32
+}
+
+/// Read side of the master spectrum analyser, see [`crate::audio::SoundMixer::enable_spectrum`]
+#[derive(Clone)]
+pub struct SpectrumAnalyzer(Arc<SpectrumShared>);
+impl SpectrumAnalyzer {
+    pub fn bin_count(&self) -> usize {
+        self.0.bins.len()
+    }
+    /// center frequency of a bin in Hz
+    pub fn bin_frequency(&self, bin: usize) -> f32 {
+        let sample_rate = f32::from_bits(self.0.sample_rate.load(Ordering::Relaxed));
+        bin as f32 * sample_rate / (self.bin_count() * 2) as f32
+    }
+    /// Consistent copy of the latest magnitudes in dB, one per bin from DC up to half the sample rate
+    pub fn magnitudes_db(&self) -> Vec<f32> {
+        let mut result = vec![SILENCE_DB; self.bin_count()];
+        // the audio thread rewrites the bins in a few microseconds every few milliseconds,
+        // a torn read is retried until one falls between two updates
+        loop {
+            let before = self.0.sequence.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            for (value, bin) in result.iter_mut().zip(self.0.bins.iter()) {
+                *value = load(bin);
+            }
+            // keeps the bin loads above from moving past the second sequence load
+            fence(Ordering::Acquire);
+            if self.0.sequence.load(Ordering::Relaxed) == before {
+                return result;
+            }
+        }
+    }
+    /// Magnitudes grouped into `count` logarithmically spaced bands between `min_hz` and `max_hz`,
+    /// each one the loudest bin in it, in dB
+    pub fn bands_db(&self, count: usize, min_hz: f32, max_hz: f32) -> Vec<f32> {
+        let magnitudes = self.magnitudes_db();
+        let bin_width = self.bin_frequency(1).max(f32::EPSILON);
+        let min_hz = min_hz.max(bin_width);
+        let ratio = (max_hz.max(min_hz) / min_hz).powf(1.0 / count.max(1) as f32);
+        (0..count)
+            .map(|band| {
+                let low = min_hz * ratio.powi(band as i32);
+                let high = low * ratio;
+                let first = (low / bin_width) as usize;
+                let last = ((high / bin_width) as usize).max(first + 1).min(magnitudes.len());
+                magnitudes.get(first..last)
+                    .map(|bins| bins.iter().cloned().fold(SILENCE_DB, f32::max))
+                    .unwrap_or(SILENCE_DB)
+            })
+            .collect()
+    }
+}
+
+/// audio thread side of a [`SpectrumAnalyzer`], everything is allocated up front on the game thread
+pub(crate) struct SpectrumState {
+    shared: Arc<SpectrumShared>,
+    history: Vec<f32>,
+    position: usize,
+    since_update: usize,
+    window: Vec<f32>,
+    twiddles: Vec<(f32, f32)>,
+    re: Vec<f32>,
+    im: Vec<f32>
+}
+impl SpectrumState {
+    /// `size` is rounded to a power of two between 64 and 8192
+    pub(crate) fn new(size: usize) -> (Self, SpectrumAnalyzer) {
+        let size = size.clamp(64, 8192).next_power_of_two();
+        let shared = Arc::new(SpectrumShared {
+            sequence: AtomicU64::new(0),
+            bins: (0..size / 2).map(|_| AtomicU32::new(SILENCE_DB.to_bits())).collect(),
+            sample_rate: AtomicU32::new(44100f32.to_bits())
+        });
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+            .collect();
+        let twiddles = (0..size / 2)
+            .map(|i| {
+                let angle = -2.0 * PI * i as f32 / size as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        let state = Self {
+            shared: shared.clone(),
+            history: vec![0.0; size],
+            position: 0,
+            since_update: 0,
+            window,
+            twiddles,
+            re: vec![0.0; size],
+            im: vec![0.0; size]
+        };
+        (state, SpectrumAnalyzer(shared))
+    }
+
+    pub(crate) fn add(&mut self, frame: [f32; 2], sample_rate: f32) {
+        let size = self.history.len();
+        self.history[self.position] = (frame[0] + frame[1]) * 0.5;
+        self.position = (self.position + 1) % size;
+        self.since_update += 1;
+        // half overlapping windows
+        if self.since_update >= size / 2 {
+            self.since_update = 0;
+            self.analyze();
+            self.shared.sample_rate.store(sample_rate.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    fn analyze(&mut self) {
+        let size = self.history.len();
+        for i in 0..size {
+            self.re[i] = self.history[(self.position + i) % size] * self.window[i];
+            self.im[i] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im, &self.twiddles);
+
+        // hann window halves the amplitude
+        let scale = 4.0 / size as f32;
+        // the only writer, readers retry while the sequence is odd or has changed
+        let sequence = self.shared.sequence.load(Ordering::Relaxed);
+        self.shared.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (i, bin) in self.shared.bins.iter().enumerate() {
+            let magnitude = (self.re[i] * self.re[i] + self.im[i] * self.im[i]).sqrt() * scale;
+            bin.store(linear_to_db(magnitude).to_bits(), Ordering::Relaxed);
+        }
+        self.shared.sequence.store(sequence + 2, Ordering::Release);
+    }
+}
+
+/// in-place iterative radix-2 FFT, the length must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32], twiddles: &[(f32, f32)]) {
+    let size = re.len();
+    let bits = size.trailing_zeros();
+    for i in 0..size {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= size {
+        let half = length / 2;
+        let step = size / length;
+        for start in (0..size).step_by(length) {
+            for k in 0..half {
+                let (w_re, w_im) = twiddles[k * step];
+                let (a, b) = (start + k, start + k + half);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        length *= 2;
+    }
+}
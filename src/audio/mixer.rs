@@ -1,23 +1,98 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Instant;
 use crate::audio::sound_driver::SoundDriver;
 use rom_loaders_rs::multimedia::WavContent;
-use std::io::Cursor;
+use crate::audio::source::{SoundSource, SampleSource, PitchedSource, SourceState};
+use crate::audio::capture::MixerCapture;
+use crate::audio::bus::{Bus, BusId, Ducking, DuckingId, DuckingState, DuckTrigger};
+use crate::audio::snapshot::{MixerSnapshot, VoiceSnapshot};
+use crate::audio::bank::SoundBank;
+use crate::audio::meter::{LevelMeter, MeterAccumulator, SpectrumAnalyzer, SpectrumState};
+use crate::audio::volume::{Gain, VolumeCurve, VolumeError, Level};
+use crate::audio::effects::{Compressor, EqBand, EqState, CompressorState, EffectError};
+use crate::audio::output::{AudioConfig, OutputInfo, RenderAhead};
+use crate::audio::debug::{MixerDebugInfo, MixerStats, VoiceDebugInfo};
+
+pub(crate) struct PlayRequest {
+    id: SoundId,
+    source: Box<dyn SoundSource>,
+    gain: f32,
+    start_frame: Option<u64>,
+    bus: BusId,
+    tag: Option<Arc<str>>,
+    asset: Option<Arc<str>>,
+    meter: Option<LevelMeter>
+}
 
 pub(crate) enum MixerMessage {
-    Play(SoundId, Sound, Volume),
-    SetVolume(SoundId, Volume),
+    Play(PlayRequest),
+    PlayGroup(Vec<PlayRequest>),
+    SetGain(SoundId, f32),
     StreamContent(SoundId, Vec<f32>),
-    SetVolumeSelf(Volume),
+    SetMasterGain(f32),
     Stop(SoundId),
+    AddCapture(Sender<Vec<f32>>),
+    AddBus(BusId, LevelMeter),
+    SetBusGain(BusId, f32),
+    AddDucking(DuckingId, Ducking),
+    RemoveDucking(DuckingId),
+    SetBusEq(BusId, Vec<EqBand>),
+    SetBusCompressor(BusId, Option<Compressor>),
+    SetPaused(SoundId, bool),
+    QueryVoices(Sender<Vec<VoiceState>>),
+    SetSpectrum(Option<Box<SpectrumState>>),
+}
+
+/// voice state reported by the audio thread
+pub(crate) struct VoiceState {
+    pub(crate) id: SoundId,
+    pub(crate) asset: Option<Arc<str>>,
+    pub(crate) position: Option<usize>,
+    pub(crate) gain: f32,
+    pub(crate) pitch: f32,
+    pub(crate) looping: bool,
+    pub(crate) paused: bool,
+    pub(crate) bus: BusId,
+    pub(crate) tag: Option<Arc<str>>,
+    pub(crate) effective_gain: f32,
+    pub(crate) playback_style: Option<PlaybackStyle>,
+    pub(crate) starving: bool,
+    pub(crate) scheduled_frame: Option<u64>
 }
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
 pub struct SoundId(usize);
 
-#[derive(Clone, Copy, Debug)]
+/// Slider position in `0.0..=1.0`, turned into a gain by the mixer's [`VolumeCurve`]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Volume(pub f32);
+impl Volume {
+    pub fn new(volume: f32) -> Result<Self, VolumeError> {
+        Volume(volume).validate()
+    }
+    /// Clamps into `0.0..=1.0` instead of rejecting, NaN is silence
+    pub fn clamped(volume: f32) -> Self {
+        if volume.is_nan() {
+            Volume(0.0)
+        } else {
+            Volume(volume.clamp(0.0, 1.0))
+        }
+    }
+    pub fn validate(self) -> Result<Self, VolumeError> {
+        if !self.0.is_finite() {
+            return Err(VolumeError::NotFinite);
+        }
+        if self.0 < 0.0 || self.0 > 1.0 {
+            return Err(VolumeError::VolumeOutOfRange(self.0));
+        }
+        Ok(self)
+    }
+}
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum PlaybackStyle {
     Once,
     Looped,
@@ -29,6 +104,25 @@ struct SampleRateCorrection {
     progress_increment_amount: usize,
     ticks_pre_increment: usize
 }
+impl SampleRateCorrection {
+    fn for_sample_rate(sample_rate: f32) -> Self {
+        let sample_rate = sample_rate as usize;
+        let progress_increment_amount = if sample_rate > 44100 {
+            sample_rate / 44100
+        } else {
+            1
+        };
+        let ticks_pre_increment = if sample_rate >= 44100 {
+            1
+        } else {
+            44100 / sample_rate
+        };
+        SampleRateCorrection {
+            progress_increment_amount,
+            ticks_pre_increment
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Sound {
@@ -40,7 +134,7 @@ pub struct Sound {
 impl From<&WavContent> for Sound {
     fn from(content: &WavContent) -> Self {
         let samples = content.data.iter()
-            .map(|sample| *sample as f32 / std::i16::MAX as f32)
+            .map(|sample| *sample as f32 / 32768.0)
             .collect();
         Self {
             sample_rate: content.fmt.sampling_rate as f32,
@@ -54,49 +148,138 @@ impl Sound {
     pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
         Self::from_bytes_ext(bytes, PlaybackStyle::Once)
     }
+    /// Decodes a WAV file: 8/16/24/32-bit PCM, float, IMA ADPCM or Microsoft ADPCM
     pub fn from_bytes_ext(bytes: &[u8], playback_style: PlaybackStyle) -> std::io::Result<Self> {
-        let mut cursor = Cursor::new(bytes);
-        let content = WavContent::read(&mut cursor)?;
-        let sound = Sound::from(&content);
+        let sound = super::wav::decode(bytes)?;
         Ok(Self {
             playback_style,
             ..sound
         })
     }
-    fn get_sample_rate_correction(&self) -> SampleRateCorrection {
-        let sample_rate = self.sample_rate as usize;
-        let progress_increment_amount = if sample_rate > 44100 {
-            sample_rate / 44100
-        } else {
-            1
-        } * self.channels as usize;
-        let ticks_pre_increment = if sample_rate >= 44100 {
-            1
-        } else {
-            44100 / sample_rate
-        } * 2;
-        SampleRateCorrection {
-            progress_increment_amount,
-            ticks_pre_increment
-        }
+    /// Serializes the sound as a 32-bit float RIFF WAV
+    pub fn write_wav<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        super::wav::encode(self, writer)
     }
 }
 
 struct SoundInternal {
-    data: Sound,
-    progress: usize,
-    volume: Volume,
-    ear: EarState,
+    source: Box<dyn SoundSource>,
+    frame: [f32; 2],
+    frames_to_pull: usize,
+    gain: f32,
     sample_rate_correction: SampleRateCorrection,
-    ticks: usize
+    ticks: usize,
+    bus: BusId,
+    tag: Option<Arc<str>>,
+    asset: Option<Arc<str>>,
+    paused: bool,
+    starving: bool,
+    meter: Option<MeterAccumulator>
+}
+
+/// state published by the audio thread for the game thread to read
+pub(crate) struct MixerShared {
+    rendered_frames: AtomicU64,
+    delivered_frames: AtomicU64,
+    buffer_frames: AtomicU64,
+    /// `f32` bits
+    sample_rate: AtomicU32,
+    /// nanoseconds since `epoch` when the last buffer was handed to the device
+    delivered_at: AtomicU64,
+    epoch: Instant,
+    /// length of the render-ahead queue in frames
+    pub(crate) queue_frames: AtomicU64,
+    pub(crate) underruns: AtomicU64,
+    messages_processed: AtomicU64,
+    voices_culled: AtomicU64,
+    voices_stopped: AtomicU64
+}
+impl MixerShared {
+    fn new() -> Self {
+        Self {
+            rendered_frames: AtomicU64::new(0),
+            delivered_frames: AtomicU64::new(0),
+            buffer_frames: AtomicU64::new(0),
+            sample_rate: AtomicU32::new(44100f32.to_bits()),
+            delivered_at: AtomicU64::new(0),
+            epoch: Instant::now(),
+            queue_frames: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            messages_processed: AtomicU64::new(0),
+            voices_culled: AtomicU64::new(0),
+            voices_stopped: AtomicU64::new(0)
+        }
+    }
+
+    /// Called once a whole output buffer was handed to the device
+    pub(crate) fn buffer_delivered(&self, frames: usize) {
+        self.buffer_frames.store(frames as u64, Ordering::Relaxed);
+        self.delivered_at.store(self.epoch.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.delivered_frames.fetch_add(frames as u64, Ordering::Release);
+    }
+}
+
+/// Cloneable handle to the mixer's playback clock.
+///
+/// Unlike wall time, it follows what actually reaches the output device,
+/// so visuals locked to it stay in sync with what the player hears.
+#[derive(Clone)]
+pub struct AudioClock(Arc<MixerShared>);
+impl AudioClock {
+    /// Monotonically increasing count of frames handed to the output device
+    pub fn delivered_frames(&self) -> u64 {
+        self.0.delivered_frames.load(Ordering::Acquire)
+    }
+    pub fn sample_rate(&self) -> f32 {
+        f32::from_bits(self.0.sample_rate.load(Ordering::Relaxed))
+    }
+    /// Estimated output latency in frames, i.e. the size of the last buffer the device asked for
+    /// plus the render-ahead queue, see [`SoundMixer::output_info`].
+    pub fn latency_frames(&self) -> u64 {
+        self.device_buffer_frames() + self.0.queue_frames.load(Ordering::Relaxed)
+    }
+    fn device_buffer_frames(&self) -> u64 {
+        self.0.buffer_frames.load(Ordering::Relaxed)
+    }
+    pub fn latency_seconds(&self) -> f32 {
+        self.latency_frames() as f32 / self.sample_rate()
+    }
+    /// Estimated frame the player is hearing right now.
+    /// Interpolated with wall time between device callbacks, never goes backwards past delivered data.
+    pub fn playback_frame(&self) -> u64 {
+        let delivered = self.delivered_frames();
+        // delivered frames already left the queue, only the device buffer is still ahead
+        let latency = self.device_buffer_frames();
+        let delivered_at = self.0.delivered_at.load(Ordering::Relaxed);
+        let now = self.0.epoch.elapsed().as_nanos() as u64;
+        let since_delivery = now.saturating_sub(delivered_at) as f64 / 1_000_000_000.0;
+        let interpolated = (since_delivery * self.sample_rate() as f64) as u64;
+        (delivered.saturating_sub(latency) + interpolated.min(latency)).min(delivered)
+    }
+    pub fn playback_seconds(&self) -> f64 {
+        self.playback_frame() as f64 / self.sample_rate() as f64
+    }
 }
 
 pub(crate) struct MixerInternal {
     sample_rate: f32,
     sounds: HashMap<SoundId, SoundInternal>,
+    scheduled: Vec<(u64, SoundId, SoundInternal)>,
     dead_sounds: Vec<SoundId>,
-    volume: Volume,
+    master_gain: f32,
     ear: EarState,
+    frame: [f32; 2],
+    frame_count: u64,
+    shared: Arc<MixerShared>,
+    captures: Vec<Sender<Vec<f32>>>,
+    /// master output since the last delivered buffer, sent to `captures`
+    captured: Vec<f32>,
+    /// indexed by [`BusId`], the first one is the master
+    buses: Vec<Bus>,
+    duckings: Vec<DuckingState>,
+    /// frames per published meter value
+    meter_window: usize,
+    spectrum: Option<Box<SpectrumState>>
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -115,29 +298,105 @@ impl EarState {
 
 pub struct SoundMixer {
     driver: super::sound_driver::SoundDriver,
-    uid: usize
+    shared: Arc<MixerShared>,
+    uid: usize,
+    bus_names: Vec<String>,
+    bus_meters: Vec<LevelMeter>,
+    ducking_uid: usize,
+    volume_curve: VolumeCurve,
+    plays_rejected: u64
 }
 
 pub struct PlaybackBuilder {
-    sound: Option<Sound>,
-    volume: Volume
+    source: Option<Box<dyn SoundSource>>,
+    level: Level,
+    trim: Gain,
+    start_frame: Option<u64>,
+    bus: BusId,
+    tag: Option<Arc<str>>,
+    asset: Option<Arc<str>>,
+    meter: Option<LevelMeter>
 }
 impl PlaybackBuilder {
     pub fn new() -> Self {
         Self {
-            sound: None,
-            volume: Volume(1.0)
+            source: None,
+            level: Level::Gain(Gain::UNITY),
+            trim: Gain::UNITY,
+            start_frame: None,
+            bus: BusId::MASTER,
+            tag: None,
+            asset: None,
+            meter: None
         }
     }
+    /// Volume mapped through the mixer's [`VolumeCurve`]
     pub fn with_volume(self, volume: Volume) -> Self {
         Self {
-            volume,
+            level: Level::Volume(volume),
+            ..self
+        }
+    }
+    /// Linear gain, e.g. `Gain::from_db(-6.0)?`
+    pub fn with_gain(self, gain: Gain) -> Self {
+        Self {
+            level: Level::Gain(gain),
+            ..self
+        }
+    }
+    /// Per-asset gain applied on top of the volume, e.g. a [`crate::audio::Loudness::normalization_gain`]
+    pub fn with_trim(self, trim: Gain) -> Self {
+        Self {
+            trim,
             ..self
         }
     }
     pub fn with_sound(self, sound: Sound) -> Self {
+        self.with_source(SampleSource::new(sound))
+    }
+    /// Like [`PlaybackBuilder::with_sound`], but shares the samples instead of moving them in
+    pub fn with_shared_sound(self, sound: Arc<Sound>) -> Self {
+        self.with_source(SampleSource::shared(sound))
+    }
+    pub fn with_source(self, source: impl SoundSource + 'static) -> Self {
+        Self {
+            source: Some(Box::new(source)),
+            ..self
+        }
+    }
+    /// Start the sound exactly at the given mixer frame (see [`SoundMixer::current_frame`]).
+    /// If the frame has already passed when the audio thread gets the request, the sound starts right away.
+    pub fn with_start_frame(self, start_frame: u64) -> Self {
+        Self {
+            start_frame: Some(start_frame),
+            ..self
+        }
+    }
+    /// Bus the sound plays through, see [`SoundMixer::add_bus`]
+    pub fn with_bus(self, bus: BusId) -> Self {
+        Self {
+            bus,
+            ..self
+        }
+    }
+    /// Tag the voice, e.g. to trigger a [`Ducking`] with [`DuckTrigger::Tag`]
+    pub fn with_tag(self, tag: &str) -> Self {
+        Self {
+            tag: Some(tag.into()),
+            ..self
+        }
+    }
+    /// Name of the [`SoundBank`] asset being played, which makes the voice part of [`SoundMixer::snapshot`]
+    pub fn with_asset(self, asset: &str) -> Self {
+        Self {
+            asset: Some(asset.into()),
+            ..self
+        }
+    }
+    /// Publishes the levels of the voice to `meter`
+    pub fn with_meter(self, meter: LevelMeter) -> Self {
         Self {
-            sound: Some(sound),
+            meter: Some(meter),
             ..self
         }
     }
@@ -145,157 +404,741 @@ impl PlaybackBuilder {
 
 impl SoundMixer {
     pub fn new() -> SoundMixer {
-        let mut driver = SoundDriver::new(Box::new(MixerInternal {
-            sample_rate: 0.,
-            sounds: HashMap::new(),
-            dead_sounds: Vec::new(),
-            volume: Volume(1.0),
-            ear: EarState::Left
-        }));
-        driver.start();
-        SoundMixer { driver, uid: 0 }
+        Self::with_config(AudioConfig::new())
     }
 
-    pub fn new_ext(initial_volume: Volume) -> SoundMixer {
-        let mut driver = SoundDriver::new(Box::new(MixerInternal {
-            sample_rate: 0.,
-            sounds: HashMap::new(),
-            dead_sounds: Vec::new(),
-            volume: initial_volume,
-            ear: EarState::Left
-        }));
-        driver.start();
-        SoundMixer { driver, uid: 0 }
+    pub fn new_ext(initial_volume: Volume) -> Result<SoundMixer, VolumeError> {
+        Ok(Self::with_config(AudioConfig::new().with_volume(initial_volume)?))
     }
 
-    pub fn play(&mut self, playback_builder: PlaybackBuilder) -> Option<SoundId> {
-        if playback_builder.sound.is_none() {
-            None
-        } else {
-            let sound_id = SoundId(self.uid);
-            self.uid += 1;
-            self.driver.send_event(MixerMessage::Play(sound_id, playback_builder.sound.unwrap(), playback_builder.volume));
-            Some(sound_id)
+    /// Mixer with a render-ahead queue or another initial volume, see [`AudioConfig`]
+    pub fn with_config(config: AudioConfig) -> SoundMixer {
+        Self::with_driver(config, |internal, render_ahead| {
+            let mut driver = SoundDriver::new(internal, render_ahead);
+            driver.start();
+            driver
+        })
+    }
+
+    /// Mixer without an output device, for tests and bouncing audio to a file.
+    /// Nothing plays until [`SoundMixer::render`] pulls frames, so the output is fully deterministic.
+    pub fn offline(sample_rate: f32) -> SoundMixer {
+        Self::offline_with_config(sample_rate, AudioConfig::new())
+    }
+
+    /// Like [`SoundMixer::offline`], a render-ahead queue is only filled by [`SoundMixer::render_ahead`]
+    pub fn offline_with_config(sample_rate: f32, config: AudioConfig) -> SoundMixer {
+        Self::with_driver(config, |internal, render_ahead| SoundDriver::offline(internal, sample_rate, render_ahead))
+    }
+
+    fn with_driver(
+        config: AudioConfig,
+        driver: impl FnOnce(Box<MixerInternal>, RenderAhead) -> SoundDriver
+    ) -> SoundMixer {
+        let shared = Arc::new(MixerShared::new());
+        let master_meter = LevelMeter::new();
+        let volume_curve = VolumeCurve::default();
+        let master_gain = volume_curve.gain(config.initial_volume)
+            .expect("`AudioConfig::with_volume` only accepts valid volumes");
+        let internal = MixerInternal::new(master_gain.0, shared.clone(), master_meter.clone());
+        SoundMixer {
+            driver: driver(Box::new(internal), config.render_ahead),
+            shared,
+            uid: 0,
+            bus_names: vec!["master".to_string()],
+            bus_meters: vec![master_meter],
+            ducking_uid: 0,
+            volume_curve,
+            plays_rejected: 0
+        }
+    }
+
+    /// Hands the next `frames` interleaved stereo frames of an [`SoundMixer::offline`] mixer to
+    /// the imaginary device, out of the render-ahead queue when there is one.
+    /// Returns nothing for a mixer playing to a device.
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        self.driver.render(frames)
+    }
+
+    /// Mixes up to `frames` frames into the render-ahead queue of an offline mixer, returns how many fit
+    pub fn render_ahead(&mut self, frames: usize) -> usize {
+        self.driver.render_ahead(frames)
+    }
+
+    fn make_request(&mut self, playback_builder: PlaybackBuilder) -> Option<PlayRequest> {
+        let request = self.build_request(playback_builder);
+        if request.is_none() {
+            self.plays_rejected += 1;
+        }
+        request
+    }
+
+    fn build_request(&mut self, playback_builder: PlaybackBuilder) -> Option<PlayRequest> {
+        let source = playback_builder.source?;
+        // the audio thread mixes mono and stereo only and steps through sources by their sample rate
+        let sample_rate = source.sample_rate();
+        if !matches!(source.channels(), 1 | 2) || !sample_rate.is_finite() || sample_rate < 1.0 {
+            return None;
+        }
+        let gain = playback_builder.level.gain(self.volume_curve).ok()?;
+        let gain = Gain(gain.0 * playback_builder.trim.0).validate().ok()?;
+        let id = SoundId(self.uid);
+        self.uid += 1;
+        Some(PlayRequest {
+            id,
+            source,
+            gain: gain.0,
+            start_frame: playback_builder.start_frame,
+            bus: playback_builder.bus,
+            tag: playback_builder.tag,
+            asset: playback_builder.asset,
+            meter: playback_builder.meter
+        })
+    }
+
+    /// Plays a [`PlaybackBuilder`] or anything that turns into one, e.g. a [`crate::audio::SoundContainer`].
+    /// Returns `None` if there is nothing to play, the source is neither mono nor stereo,
+    /// has no usable sample rate, or the volume is out of range.
+    pub fn play(&mut self, playback_builder: impl Into<PlaybackBuilder>) -> Option<SoundId> {
+        let request = self.make_request(playback_builder.into())?;
+        let sound_id = request.id;
+        self.driver.send_event(MixerMessage::Play(request));
+        Some(sound_id)
+    }
+
+    /// Starts several sounds on the very same frame, e.g. stems of a multi-track piece of music.
+    /// Builders with a start frame are scheduled as usual, all the others start together.
+    pub fn play_group(&mut self, playback_builders: Vec<PlaybackBuilder>) -> Vec<Option<SoundId>> {
+        let mut requests = Vec::with_capacity(playback_builders.len());
+        let mut ids = Vec::with_capacity(playback_builders.len());
+        for builder in playback_builders {
+            let request = self.make_request(builder);
+            ids.push(request.as_ref().map(|r| r.id));
+            requests.extend(request);
+        }
+        if !requests.is_empty() {
+            self.driver.send_event(MixerMessage::PlayGroup(requests));
+        }
+        ids
+    }
+
+    /// Number of frames the mixer has rendered so far, the time base for [`PlaybackBuilder::with_start_frame`]
+    pub fn current_frame(&self) -> u64 {
+        self.shared.rendered_frames.load(Ordering::Relaxed)
+    }
+
+    /// Clock following the frames actually delivered to the output device
+    pub fn clock(&self) -> AudioClock {
+        AudioClock(self.shared.clone())
+    }
+
+    /// Effective sample rate, buffer sizes and latency of the output
+    pub fn output_info(&self) -> OutputInfo {
+        OutputInfo {
+            sample_rate: f32::from_bits(self.shared.sample_rate.load(Ordering::Relaxed)),
+            device_buffer_frames: self.shared.buffer_frames.load(Ordering::Relaxed),
+            queue_frames: self.shared.queue_frames.load(Ordering::Relaxed),
+            underruns: self.shared.underruns.load(Ordering::Relaxed)
         }
     }
 
+    /// Starts recording the master output into memory, see [`MixerCapture`]
+    pub fn capture_to_memory(&mut self) -> MixerCapture {
+        MixerCapture::to_memory(self.start_capture(), self.clock())
+    }
+
+    /// Starts recording the master output into a WAV file, see [`MixerCapture`]
+    pub fn capture_to_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> std::io::Result<MixerCapture> {
+        let file = std::fs::File::create(path)?;
+        MixerCapture::to_file(file, self.start_capture(), self.clock())
+    }
+
+    fn start_capture(&mut self) -> std::sync::mpsc::Receiver<Vec<f32>> {
+        let (sender, receiver) = channel();
+        self.driver.send_event(MixerMessage::AddCapture(sender));
+        receiver
+    }
+
     pub fn stream_sound(&mut self, sound_id: SoundId, content: Vec<f32>) {
         self.driver.send_event(MixerMessage::StreamContent(sound_id, content))
     }
 
-    pub fn set_volume(&mut self, sound_id: SoundId, volume: Volume) {
-        self.driver.send_event(MixerMessage::SetVolume(sound_id, volume));
+    /// Curve used to map every [`Volume`] given from now on, doesn't touch playing sounds
+    pub fn set_volume_curve(&mut self, volume_curve: VolumeCurve) {
+        self.volume_curve = volume_curve;
+    }
+
+    pub fn volume_curve(&self) -> VolumeCurve {
+        self.volume_curve
+    }
+
+    pub fn set_volume(&mut self, sound_id: SoundId, volume: Volume) -> Result<(), VolumeError> {
+        let gain = self.volume_curve.gain(volume)?;
+        self.set_gain(sound_id, gain)
     }
 
-    pub fn set_volume_self(&mut self, volume: Volume) {
-        self.driver.send_event(MixerMessage::SetVolumeSelf(volume));
+    /// For volumes computed out of valid factors, e.g. a fade, which rounding can push just out of range
+    pub(crate) fn set_volume_clamped(&mut self, sound_id: SoundId, volume: f32) {
+        let gain = self.volume_curve.gain(Volume::clamped(volume)).expect("a clamped volume is in range");
+        self.driver.send_event(MixerMessage::SetGain(sound_id, gain.0));
+    }
+
+    pub fn set_gain(&mut self, sound_id: SoundId, gain: Gain) -> Result<(), VolumeError> {
+        let gain = gain.validate()?;
+        self.driver.send_event(MixerMessage::SetGain(sound_id, gain.0));
+        Ok(())
+    }
+
+    pub fn set_volume_self(&mut self, volume: Volume) -> Result<(), VolumeError> {
+        let gain = self.volume_curve.gain(volume)?;
+        self.set_master_gain(gain)
+    }
+
+    pub fn set_master_gain(&mut self, gain: Gain) -> Result<(), VolumeError> {
+        let gain = gain.validate()?;
+        self.driver.send_event(MixerMessage::SetMasterGain(gain.0));
+        Ok(())
     }
 
     pub fn stop(&mut self, sound_id: SoundId) {
         self.driver.send_event(MixerMessage::Stop(sound_id));
     }
 
+    pub fn pause(&mut self, sound_id: SoundId) {
+        self.driver.send_event(MixerMessage::SetPaused(sound_id, true));
+    }
+
+    pub fn resume(&mut self, sound_id: SoundId) {
+        self.driver.send_event(MixerMessage::SetPaused(sound_id, false));
+    }
+
+    /// asks the audio thread for the state of its voices in the order they were played,
+    /// waiting up to a few device buffers
+    fn query_voices(&mut self) -> std::io::Result<Vec<VoiceState>> {
+        let (sender, receiver) = channel();
+        self.driver.send_event(MixerMessage::QueryVoices(sender));
+        let mut voices = receiver.recv_timeout(std::time::Duration::from_millis(500))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "audio thread did not respond"))?;
+        voices.sort_by_key(|voice| voice.id.0);
+        Ok(voices)
+    }
+
+    /// Captures the voices playing [`SoundBank`] assets, e.g. for a savegame.
+    /// Add the music with [`crate::audio::MusicPlayer::snapshot`].
+    /// Blocks until the audio thread answers, which takes about one device buffer.
+    pub fn snapshot(&mut self) -> std::io::Result<MixerSnapshot> {
+        let voices = self.query_voices()?
+            .into_iter()
+            .filter(|voice| voice.scheduled_frame.is_none())
+            .filter_map(|voice| Some(VoiceSnapshot {
+                asset: voice.asset?.to_string(),
+                position: voice.position?,
+                gain: voice.gain,
+                pitch: voice.pitch,
+                looping: voice.looping,
+                paused: voice.paused,
+                bus: self.bus_name(voice.bus).unwrap_or("master").to_string(),
+                tag: voice.tag.map(|tag| tag.to_string())
+            }))
+            .collect();
+        Ok(MixerSnapshot { voices, music: None })
+    }
+
+    /// Running totals of the mixer, cheap enough to show every frame
+    pub fn stats(&self) -> MixerStats {
+        MixerStats {
+            messages_processed: self.shared.messages_processed.load(Ordering::Relaxed),
+            voices_culled: self.shared.voices_culled.load(Ordering::Relaxed),
+            voices_stopped: self.shared.voices_stopped.load(Ordering::Relaxed),
+            plays_rejected: self.plays_rejected,
+            underruns: self.shared.underruns.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Every playing and scheduled voice along with [`SoundMixer::stats`], for developer consoles.
+    /// Blocks until the audio thread answers, like [`SoundMixer::snapshot`].
+    pub fn debug_info(&mut self) -> std::io::Result<MixerDebugInfo> {
+        let voices = self.query_voices()?
+            .into_iter()
+            .map(|voice| VoiceDebugInfo {
+                id: voice.id,
+                asset: voice.asset.map(|asset| asset.to_string()),
+                tag: voice.tag.map(|tag| tag.to_string()),
+                position: voice.position,
+                gain: voice.gain,
+                effective_gain: voice.effective_gain,
+                bus: voice.bus,
+                bus_name: self.bus_name(voice.bus).unwrap_or("master").to_string(),
+                playback_style: voice.playback_style,
+                looping: voice.looping,
+                paused: voice.paused,
+                starving: voice.starving,
+                scheduled_frame: voice.scheduled_frame
+            })
+            .collect();
+        Ok(MixerDebugInfo {
+            voices,
+            stats: self.stats()
+        })
+    }
+
+    /// Starts the voices of a snapshot again, loading their assets from `bank`.
+    /// Buses are matched by name, voices of unknown buses go to the master.
+    pub fn restore(&mut self, snapshot: &MixerSnapshot, bank: &mut SoundBank) -> std::io::Result<Vec<SoundId>> {
+        let mut ids = Vec::with_capacity(snapshot.voices.len());
+        for voice in snapshot.voices.iter() {
+            let sound = bank.sound(voice.asset.as_str())?;
+            let style = if voice.looping { PlaybackStyle::Looped } else { PlaybackStyle::Once };
+            let mut source = SampleSource::shared(sound).with_playback_style(style);
+            source.seek(voice.position);
+            let bus = self.bus_names.iter()
+                .position(|name| *name == voice.bus)
+                .map(BusId)
+                .unwrap_or(BusId::MASTER);
+            // keeps the pitch a container picked for the voice
+            let builder = if voice.pitch == 1.0 {
+                PlaybackBuilder::new().with_source(source)
+            } else {
+                PlaybackBuilder::new().with_source(PitchedSource::new(source, voice.pitch))
+            };
+            let mut builder = builder
+                .with_asset(&voice.asset)
+                .with_gain(Gain(voice.gain))
+                .with_bus(bus);
+            if let Some(tag) = &voice.tag {
+                builder = builder.with_tag(tag);
+            }
+            if let Some(id) = self.play(builder) {
+                if voice.paused {
+                    self.pause(id);
+                }
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Adds a bus summed into the master, e.g. for music, ambience or dialogue
+    pub fn add_bus(&mut self, name: &str) -> BusId {
+        let bus = BusId(self.bus_names.len());
+        let meter = LevelMeter::new();
+        self.bus_names.push(name.to_string());
+        self.bus_meters.push(meter.clone());
+        self.driver.send_event(MixerMessage::AddBus(bus, meter));
+        bus
+    }
+
+    /// Levels of a bus after its volume and ducking, [`BusId::MASTER`] meters the final mix
+    pub fn bus_meter(&self, bus: BusId) -> Option<LevelMeter> {
+        self.bus_meters.get(bus.0).cloned()
+    }
+
+    /// Starts analysing the master output, `size` is the FFT length and gets rounded to a power of two.
+    /// Replaces the analyser enabled before.
+    pub fn enable_spectrum(&mut self, size: usize) -> SpectrumAnalyzer {
+        let (state, analyzer) = SpectrumState::new(size);
+        self.driver.send_event(MixerMessage::SetSpectrum(Some(Box::new(state))));
+        analyzer
+    }
+
+    pub fn disable_spectrum(&mut self) {
+        self.driver.send_event(MixerMessage::SetSpectrum(None));
+    }
+
+    pub fn bus_name(&self, bus: BusId) -> Option<&str> {
+        self.bus_names.get(bus.0).map(|name| name.as_str())
+    }
+
+    /// Volume of a bus, [`BusId::MASTER`] is the same as [`SoundMixer::set_volume_self`]
+    pub fn set_bus_volume(&mut self, bus: BusId, volume: Volume) -> Result<(), VolumeError> {
+        let gain = self.volume_curve.gain(volume)?;
+        self.set_bus_gain(bus, gain)
+    }
+
+    pub fn set_bus_gain(&mut self, bus: BusId, gain: Gain) -> Result<(), VolumeError> {
+        if bus == BusId::MASTER {
+            return self.set_master_gain(gain);
+        }
+        let gain = gain.validate()?;
+        self.driver.send_event(MixerMessage::SetBusGain(bus, gain.0));
+        Ok(())
+    }
+
+    /// Replaces the EQ of a bus, an empty list turns it off
+    pub fn set_bus_eq(&mut self, bus: BusId, bands: Vec<EqBand>) -> Result<(), EffectError> {
+        for band in bands.iter() {
+            band.validate()?;
+        }
+        self.driver.send_event(MixerMessage::SetBusEq(bus, bands));
+        Ok(())
+    }
+
+    /// Compresses a bus after its EQ, `None` turns the compressor off
+    pub fn set_bus_compressor(&mut self, bus: BusId, compressor: Option<Compressor>) -> Result<(), EffectError> {
+        let compressor = compressor.map(Compressor::validate).transpose()?;
+        self.driver.send_event(MixerMessage::SetBusCompressor(bus, compressor));
+        Ok(())
+    }
+
+    pub fn add_ducking(&mut self, ducking: Ducking) -> DuckingId {
+        let id = DuckingId(self.ducking_uid);
+        self.ducking_uid += 1;
+        self.driver.send_event(MixerMessage::AddDucking(id, ducking));
+        id
+    }
+
+    pub fn remove_ducking(&mut self, ducking: DuckingId) {
+        self.driver.send_event(MixerMessage::RemoveDucking(ducking));
+    }
+
     pub fn frame(&mut self) {
         self.driver.frame();
     }
 }
 
+/// FNV-1a hash over the exact bits of rendered samples, to compare long renders in tests
+pub fn samples_checksum(samples: &[f32]) -> u64 {
+    samples.iter().fold(0xcbf2_9ce4_8422_2325, |hash, sample| {
+        sample.to_bits().to_le_bytes().iter().fold(hash, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    })
+}
+
 impl MixerInternal {
+    fn new(master_gain: f32, shared: Arc<MixerShared>, master_meter: LevelMeter) -> Self {
+        Self {
+            sample_rate: 0.,
+            sounds: HashMap::new(),
+            scheduled: Vec::new(),
+            dead_sounds: Vec::new(),
+            master_gain,
+            ear: EarState::Left,
+            frame: [0.0; 2],
+            frame_count: 0,
+            shared,
+            captures: Vec::new(),
+            captured: Vec::new(),
+            buses: vec![Bus::new(master_meter)],
+            duckings: Vec::new(),
+            meter_window: 44100 / 20,
+            spectrum: None
+        }
+    }
+
     pub(crate)fn init(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate;
+        self.meter_window = (sample_rate / 20.0).max(1.0) as usize;
+        self.shared.sample_rate.store(sample_rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn shared(&self) -> Arc<MixerShared> {
+        self.shared.clone()
+    }
+
+    /// Called by the driver once a whole output buffer was filled
+    pub(crate) fn buffer_delivered(&mut self, frames: usize) {
+        self.shared.buffer_delivered(frames);
+        self.flush_captures();
+    }
+
+    /// Hands the frames rendered since the last call to the captures
+    pub(crate) fn flush_captures(&mut self) {
+        if !self.captures.is_empty() {
+            let captured = std::mem::take(&mut self.captured);
+            // a dropped capture disconnects its channel
+            self.captures.retain(|capture| capture.send(captured.clone()).is_ok());
+        }
+    }
+
+    fn start_sound(&mut self, request: PlayRequest) {
+        let PlayRequest { id, source, gain, start_frame, bus, tag, asset, meter } = request;
+        // checked by `build_request`, the audio thread must not panic
+        debug_assert!(source.channels() == 1 || source.channels() == 2);
+        let sample_rate_correction = SampleRateCorrection::for_sample_rate(source.sample_rate());
+        let sound = SoundInternal {
+            source,
+            frame: [0.0; 2],
+            frames_to_pull: 1,
+            gain,
+            sample_rate_correction,
+            ticks: sample_rate_correction.ticks_pre_increment,
+            bus,
+            tag,
+            asset,
+            paused: false,
+            starving: false,
+            meter: meter.map(MeterAccumulator::new)
+        };
+        match start_frame {
+            Some(start_frame) if start_frame > self.frame_count => self.scheduled.push((start_frame, id, sound)),
+            _ => {
+                self.sounds.insert(id, sound);
+            }
+        }
     }
 
     pub(crate) fn handle_event(&mut self, evt: MixerMessage) {
+        self.shared.messages_processed.fetch_add(1, Ordering::Relaxed);
         match evt {
-            MixerMessage::Play(id, sound, volume) => {
-                assert!(volume.0 <= 1.0);
-                let sample_rate_correction = sound.get_sample_rate_correction();
-                self.sounds.insert(
-                    id,
-                    SoundInternal {
-                        data: sound,
-                        progress: 0,
-                        volume,
-                        ear: EarState::Left,
-                        sample_rate_correction,
-                        ticks: sample_rate_correction.ticks_pre_increment
-                    },
-                );
+            MixerMessage::Play(request) => self.start_sound(request),
+            MixerMessage::PlayGroup(requests) => {
+                for request in requests {
+                    self.start_sound(request);
+                }
             },
             MixerMessage::StreamContent(id, content) => {
                 if let Some(sound) = self.sounds.get_mut(&id) {
-                    assert_eq!(sound.data.playback_style, PlaybackStyle::Streamed);
-                    sound.data.samples.extend(content);
+                    sound.source.stream_content(content);
                 }
             }
-            MixerMessage::SetVolume(id, volume) => {
-                if let Some(sound) = self.sounds.get_mut(&id) {
-                    assert!(volume.0 <= 1.0);
-                    sound.volume = volume;
+            MixerMessage::SetGain(id, gain) => {
+                let scheduled = self.scheduled.iter_mut()
+                    .find(|(_, scheduled_id, _)| *scheduled_id == id)
+                    .map(|(_, _, sound)| sound);
+                if let Some(sound) = self.sounds.get_mut(&id).or(scheduled) {
+                    sound.gain = gain;
                 }
             },
-            MixerMessage::SetVolumeSelf( volume) => {
-                self.volume = volume;
+            MixerMessage::SetMasterGain(gain) => {
+                self.master_gain = gain;
             },
             MixerMessage::Stop(id) => {
-                self.sounds.remove(&id);
+                let scheduled = self.scheduled.len();
+                self.scheduled.retain(|(_, scheduled_id, _)| *scheduled_id != id);
+                if self.sounds.remove(&id).is_some() || self.scheduled.len() < scheduled {
+                    self.shared.voices_stopped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            MixerMessage::AddCapture(sender) => {
+                self.captures.push(sender);
+            }
+            MixerMessage::AddBus(bus, meter) => {
+                debug_assert_eq!(bus.0, self.buses.len());
+                self.buses.push(Bus::new(meter));
+            }
+            MixerMessage::SetBusGain(bus, gain) => {
+                if let Some(bus) = self.buses.get_mut(bus.0) {
+                    bus.gain = gain;
+                }
+            }
+            MixerMessage::SetBusEq(bus, bands) => {
+                let sample_rate = self.sample_rate;
+                if let Some(bus) = self.buses.get_mut(bus.0) {
+                    bus.eq = if bands.is_empty() { None } else { Some(EqState::new(&bands, sample_rate)) };
+                }
+            }
+            MixerMessage::SetBusCompressor(bus, compressor) => {
+                let sample_rate = self.sample_rate;
+                if let Some(bus) = self.buses.get_mut(bus.0) {
+                    bus.compressor = compressor.map(|compressor| CompressorState::new(compressor, sample_rate));
+                }
+            }
+            MixerMessage::AddDucking(id, ducking) => {
+                self.duckings.push(DuckingState::new(id, ducking, self.sample_rate));
+            }
+            MixerMessage::RemoveDucking(id) => {
+                self.duckings.retain(|ducking| ducking.id != id);
+                self.update_duck_gains();
+            }
+            MixerMessage::SetPaused(id, paused) => {
+                let scheduled = self.scheduled.iter_mut()
+                    .find(|(_, scheduled_id, _)| *scheduled_id == id)
+                    .map(|(_, _, sound)| sound);
+                if let Some(sound) = self.sounds.get_mut(&id).or(scheduled) {
+                    sound.paused = paused;
+                }
+            }
+            MixerMessage::QueryVoices(sender) => {
+                let playing = self.sounds.iter().map(|(id, sound)| (*id, sound, None));
+                let scheduled = self.scheduled.iter().map(|(frame, id, sound)| (*id, sound, Some(*frame)));
+                let voices = playing.chain(scheduled)
+                    .map(|(id, sound, scheduled_frame)| VoiceState {
+                        id,
+                        asset: sound.asset.clone(),
+                        position: sound.source.position(),
+                        gain: sound.gain,
+                        looping: sound.source.is_looping(),
+                        paused: sound.paused,
+                        bus: sound.bus,
+                        tag: sound.tag.clone(),
+                        effective_gain: sound.gain * self.bus_gain(sound.bus),
+                        playback_style: sound.source.playback_style(),
+                        pitch: sound.source.pitch(),
+                        starving: sound.starving,
+                        scheduled_frame
+                    })
+                    .collect();
+                let _ = sender.send(voices);
+            }
+            MixerMessage::SetSpectrum(spectrum) => {
+                self.spectrum = spectrum;
             }
         }
     }
 
-    pub(crate) fn next_value(&mut self) -> f32 {
-        let mut value = 0.;
+    fn start_scheduled(&mut self) {
+        let mut i = 0;
+        while i < self.scheduled.len() {
+            if self.scheduled[i].0 <= self.frame_count {
+                let (_, id, sound) = self.scheduled.swap_remove(i);
+                self.sounds.insert(id, sound);
+            } else {
+                i += 1;
+            }
+        }
+    }
 
-        for (sound_id, mut sound) in &mut self.sounds {
-            if self.ear != sound.ear {
+    fn next_frame(&mut self) -> [f32; 2] {
+        if !self.scheduled.is_empty() {
+            self.start_scheduled();
+        }
+        for bus in self.buses.iter_mut() {
+            bus.frame = [0.0; 2];
+        }
+        for ducking in self.duckings.iter_mut() {
+            ducking.level = 0.0;
+        }
+
+        for (sound_id, sound) in &mut self.sounds {
+            if sound.paused {
                 continue;
             }
+            let channels = sound.source.channels() as usize;
+            let mut state = SourceState::Ready;
+            while sound.frames_to_pull > 0 {
+                state = sound.source.next_frame(&mut sound.frame[..channels]);
+                if state != SourceState::Ready {
+                    break;
+                }
+                sound.frames_to_pull -= 1;
+            }
+            sound.starving = state == SourceState::Starving;
+            match state {
+                SourceState::Ready => {}
+                SourceState::Starving => continue,
+                SourceState::Finished => {
+                    self.dead_sounds.push(*sound_id);
+                    continue;
+                }
+            }
+            if channels == 1 {
+                sound.frame[1] = sound.frame[0];
+            }
 
-            if sound.progress >= sound.data.samples.len() {
-                match sound.data.playback_style {
-                    PlaybackStyle::Once => {
-                        self.dead_sounds.push(*sound_id);
-                        continue;
-                    }
-                    PlaybackStyle::Looped => {
-                        sound.progress = 0;
-                    }
-                    PlaybackStyle::Streamed => {
-                        continue;
+            let left = sound.frame[0] * sound.gain;
+            let right = sound.frame[1] * sound.gain;
+            if let Some(meter) = &mut sound.meter {
+                meter.add([left, right], self.meter_window);
+            }
+            // a bus of another mixer falls back to the master
+            let bus_index = if sound.bus.0 < self.buses.len() { sound.bus.0 } else { 0 };
+            let bus = &mut self.buses[bus_index];
+            bus.frame[0] += left;
+            bus.frame[1] += right;
+
+            if let Some(tag) = &sound.tag {
+                let peak = left.abs().max(right.abs());
+                for ducking in self.duckings.iter_mut() {
+                    match &ducking.trigger {
+                        DuckTrigger::Tag(trigger) if **trigger == **tag => ducking.level = ducking.level.max(peak),
+                        _ => {}
                     }
                 }
             }
 
-            let volume = sound.volume.0 * self.volume.0;
-            // it's better to remap volume exponentially
-            // so user hears difference instantly
-            let volume = volume * volume;
-
-            let next_index = match sound.data.channels {
-                1 => sound.progress,
-                2 => match sound.ear {
-                    EarState::Left => sound.progress,
-                    EarState::Right => sound.progress + 1
-                },
-                _ => unreachable!()
-            };
             sound.ticks -= 1;
-
-            value += sound.data.samples[next_index] * volume;
             if sound.ticks == 0 {
-                sound.progress += sound.sample_rate_correction.progress_increment_amount;
+                sound.frames_to_pull = sound.sample_rate_correction.progress_increment_amount;
                 sound.ticks = sound.sample_rate_correction.ticks_pre_increment;
             }
-            sound.ear.switch();
         }
 
         for sound_id in self.dead_sounds.iter() {
             self.sounds.remove(sound_id);
         }
-        self.dead_sounds.clear();
+        if !self.dead_sounds.is_empty() {
+            self.shared.voices_culled.fetch_add(self.dead_sounds.len() as u64, Ordering::Relaxed);
+            self.dead_sounds.clear();
+        }
+
+        let frame = self.mix_buses();
+
+        if !self.captures.is_empty() {
+            self.captured.extend_from_slice(&frame);
+        }
+
+        self.frame_count += 1;
+        self.shared.rendered_frames.store(self.frame_count, Ordering::Relaxed);
+
+        frame
+    }
+
+    /// sums the buses into the master and advances the duckings
+    fn mix_buses(&mut self) -> [f32; 2] {
+        let (master, buses) = self.buses.split_at_mut(1);
+        let master = &mut master[0];
+        for bus in buses.iter_mut() {
+            bus.peak = bus.frame[0].abs().max(bus.frame[1].abs());
+            let gain = bus.gain * bus.duck_gain;
+            let frame = bus.process_effects();
+            let output = [frame[0] * gain, frame[1] * gain];
+            bus.meter.add(output, self.meter_window);
+            master.frame[0] += output[0];
+            master.frame[1] += output[1];
+        }
+        master.peak = master.frame[0].abs().max(master.frame[1].abs());
+        let gain = self.master_gain * master.duck_gain;
+        let frame = master.process_effects();
+        let frame = [frame[0] * gain, frame[1] * gain];
+        master.meter.add(frame, self.meter_window);
+        if let Some(spectrum) = &mut self.spectrum {
+            spectrum.add(frame, self.sample_rate);
+        }
 
+        if !self.duckings.is_empty() {
+            for ducking in self.duckings.iter_mut() {
+                if let DuckTrigger::Bus(bus) = ducking.trigger {
+                    ducking.level = self.buses.get(bus.0).map(|bus| bus.peak).unwrap_or(0.0);
+                }
+                ducking.update();
+            }
+            self.update_duck_gains();
+        }
+        frame
+    }
+
+    /// gain from a bus to the output, including the master volume and duckings
+    fn bus_gain(&self, bus: BusId) -> f32 {
+        let master = &self.buses[0];
+        let master_gain = self.master_gain * master.duck_gain;
+        match self.buses.get(bus.0) {
+            Some(target) if bus != BusId::MASTER => target.gain * target.duck_gain * master_gain,
+            _ => master_gain
+        }
+    }
+
+    fn update_duck_gains(&mut self) {
+        for bus in self.buses.iter_mut() {
+            bus.duck_gain = 1.0;
+        }
+        for ducking in self.duckings.iter() {
+            if let Some(bus) = self.buses.get_mut(ducking.target.0) {
+                bus.duck_gain *= ducking.gain;
+            }
+        }
+    }
+
+    pub(crate) fn next_value(&mut self) -> f32 {
+        if self.ear == EarState::Left {
+            self.frame = self.next_frame();
+        }
+        let value = match self.ear {
+            EarState::Left => self.frame[0],
+            EarState::Right => self.frame[1]
+        };
         self.ear.switch();
 
         value
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,43 @@
 use std::fmt::{Display, Formatter};
 
 pub mod mixer;
+pub mod source;
+pub mod tracker;
+pub mod music;
+pub mod capture;
+pub mod bank;
+pub mod container;
+pub mod bus;
+pub mod snapshot;
+pub mod meter;
+pub mod volume;
+pub mod loudness;
+pub mod edit;
+pub mod ambient;
+pub mod effects;
+pub mod output;
+pub mod debug;
+mod byte_reader;
+mod rng;
+mod wav;
+mod filter;
 mod sound_driver;
-pub use mixer::{SoundMixer, Sound, SoundId, PlaybackBuilder};
+pub use mixer::{SoundMixer, Sound, SoundId, PlaybackBuilder, AudioClock, samples_checksum};
+pub use source::{SoundSource, SourceState, SampleSource, StreamSource, PitchedSource, Oscillator, Waveform, Adsr};
+pub use tracker::{Module, ModuleFormat, ModulePlayer, ModulePosition};
+pub use music::{MusicPlayer, MusicTrack, RepeatMode, MusicTransition};
+pub use capture::MixerCapture;
+pub use bank::{SoundBank, SoundAsset, AssetId, AssetKey, BankMemoryUsage};
+pub use container::{SoundContainer, PickMode, PitchError};
+pub use bus::{BusId, Ducking, DuckingId, DuckTrigger};
+pub use snapshot::{MixerSnapshot, VoiceSnapshot, MusicSnapshot};
+pub use meter::{LevelMeter, SpectrumAnalyzer};
+pub use volume::{Gain, VolumeCurve, VolumeError};
+pub use loudness::{Loudness, Normalization};
+pub use ambient::{AmbientSystem, AmbientEmitter, EmitterShape, EmitterId};
+pub use effects::{Compressor, EqBand, EqBandKind, EffectError};
+pub use output::{AudioConfig, RenderAhead, OutputInfo};
+pub use debug::{MixerDebugInfo, MixerStats, VoiceDebugInfo};
 pub use sound_driver::SoundDriver;
 
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,441 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::audio::mixer::{SoundMixer, Sound, SoundId, PlaybackBuilder, PlaybackStyle, Volume};
+use crate::audio::volume::VolumeError;
+use crate::audio::source::{SoundSource, SourceState, SampleSource};
+use crate::audio::tracker::{Module, ModulePlayer, ModulePosition};
+use crate::audio::rng::XorShift32;
+use crate::audio::snapshot::MusicSnapshot;
+
+#[derive(Clone)]
+enum TrackContent {
+    Sampled(Sound),
+    Tracker(Arc<Module>)
+}
+
+/// A named piece of music, either pre-decoded or a tracker module
+#[derive(Clone)]
+pub struct MusicTrack {
+    pub name: String,
+    content: TrackContent
+}
+impl MusicTrack {
+    pub fn from_sound(name: &str, sound: Sound) -> Self {
+        Self {
+            name: name.to_string(),
+            content: TrackContent::Sampled(sound)
+        }
+    }
+    pub fn from_module(name: &str, module: Arc<Module>) -> Self {
+        Self {
+            name: name.to_string(),
+            content: TrackContent::Tracker(module)
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RepeatMode {
+    /// stop after the last track of the playlist
+    Off,
+    /// loop the current track forever
+    One,
+    /// start over from the first track after the last one
+    All
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MusicTransition {
+    /// cut to the new track
+    Immediate,
+    /// crossfade into the new track right away
+    Crossfade,
+    /// wait until the current track reaches the end of its loop, then crossfade
+    AtLoopBoundary
+}
+
+/// counts frames pulled from a looped sample track so we know where its loop ends
+struct FrameCountingSource {
+    inner: SampleSource,
+    frames: Arc<AtomicUsize>
+}
+impl SoundSource for FrameCountingSource {
+    fn sample_rate(&self) -> f32 {
+        self.inner.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        let state = self.inner.next_frame(frame);
+        if state == SourceState::Ready {
+            self.frames.fetch_add(1, Ordering::Relaxed);
+        }
+        state
+    }
+
+    fn is_looping(&self) -> bool {
+        self.inner.is_looping()
+    }
+
+    fn playback_style(&self) -> Option<PlaybackStyle> {
+        self.inner.playback_style()
+    }
+}
+
+enum TrackProgress {
+    Sampled { frames: Arc<AtomicUsize>, length: usize, sample_rate: f32 },
+    Tracker(ModulePosition)
+}
+impl TrackProgress {
+    /// frames into the track
+    fn position(&self) -> usize {
+        match self {
+            TrackProgress::Sampled { frames, length, .. } => frames.load(Ordering::Relaxed) % (*length).max(1),
+            TrackProgress::Tracker(position) => position.frame()
+        }
+    }
+    fn loops(&self) -> usize {
+        match self {
+            TrackProgress::Sampled { frames, length, .. } => frames.load(Ordering::Relaxed) / (*length).max(1),
+            TrackProgress::Tracker(position) => position.loops()
+        }
+    }
+    /// Whether the first pass is over or ends within `crossfade_ms`.
+    /// The crossfade takes at most half of a sampled track, so a short one still gets to play.
+    fn is_finishing(&self, crossfade_ms: f32) -> bool {
+        if self.loops() > 0 {
+            return true;
+        }
+        match self {
+            TrackProgress::Sampled { frames, length, sample_rate } => {
+                let played = frames.load(Ordering::Relaxed);
+                let remaining_ms = length.saturating_sub(played) as f32 * 1000.0 / sample_rate;
+                let length_ms = *length as f32 * 1000.0 / sample_rate;
+                remaining_ms <= crossfade_ms.min(length_ms / 2.0)
+            }
+            TrackProgress::Tracker(_) => false
+        }
+    }
+}
+
+struct ActiveTrack {
+    name: String,
+    /// index into the playlist, `None` for tracks started with [`MusicPlayer::switch_to`]
+    playlist_index: Option<usize>,
+    sound_id: SoundId,
+    progress: TrackProgress,
+    fade: f32,
+    fade_direction: f32,
+    applied_volume: f32
+}
+impl ActiveTrack {
+    fn apply_volume(&mut self, mixer: &mut SoundMixer, volume: f32) {
+        let volume = volume * self.fade;
+        if volume != self.applied_volume {
+            mixer.set_volume_clamped(self.sound_id, volume);
+            self.applied_volume = volume;
+        }
+    }
+}
+
+struct PendingSwitch {
+    track: MusicTrack,
+    playlist_index: Option<usize>,
+    transition: MusicTransition,
+    loops_at_request: usize,
+    /// frames into the track to start at
+    start_frame: usize
+}
+
+/// Playlist based music playback on top of a [`SoundMixer`].
+///
+/// [`MusicPlayer::frame`] should be called every frame with the time passed in milliseconds,
+/// the same way [`crate::video::SmackerPlayer::frame`] is driven.
+pub struct MusicPlayer {
+    playlist: Vec<MusicTrack>,
+    play_order: Vec<usize>,
+    cursor: usize,
+    repeat: RepeatMode,
+    shuffle: bool,
+    crossfade_ms: usize,
+    volume: Volume,
+    current: Option<ActiveTrack>,
+    fading_out: Vec<ActiveTrack>,
+    pending: Option<PendingSwitch>,
+    rng: XorShift32
+}
+impl Default for MusicPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl MusicPlayer {
+    pub fn new() -> Self {
+        Self {
+            playlist: Vec::new(),
+            play_order: Vec::new(),
+            cursor: 0,
+            repeat: RepeatMode::All,
+            shuffle: false,
+            crossfade_ms: 0,
+            volume: Volume(1.0),
+            current: None,
+            fading_out: Vec::new(),
+            pending: None,
+            rng: XorShift32::from_time()
+        }
+    }
+    pub fn enqueue(&mut self, track: MusicTrack) {
+        self.playlist.push(track);
+        self.rebuild_play_order();
+    }
+    pub fn clear_playlist(&mut self) {
+        self.playlist.clear();
+        self.play_order.clear();
+        self.cursor = 0;
+    }
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+        self.rebuild_play_order();
+    }
+    pub fn set_crossfade_ms(&mut self, crossfade_ms: usize) {
+        self.crossfade_ms = crossfade_ms;
+    }
+    pub fn set_volume(&mut self, volume: Volume) -> Result<(), VolumeError> {
+        self.volume = volume.validate()?;
+        Ok(())
+    }
+    /// Name of the track that is playing (or fading in) right now
+    pub fn current_track(&self) -> Option<&str> {
+        self.current.as_ref().map(|track| track.name.as_str())
+    }
+    /// Playlist index of the current track, `None` for tracks started with [`MusicPlayer::switch_to`]
+    pub fn current_playlist_index(&self) -> Option<usize> {
+        self.current.as_ref().and_then(|track| track.playlist_index)
+    }
+    pub fn is_playing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// The current track and how far into it the music is, e.g. for
+    /// [`crate::audio::MixerSnapshot::music`] in a savegame
+    pub fn snapshot(&self) -> Option<MusicSnapshot> {
+        self.current.as_ref().map(|track| MusicSnapshot {
+            track: track.name.clone(),
+            playlist_index: track.playlist_index,
+            position: track.progress.position()
+        })
+    }
+    /// Cuts to a track captured with [`MusicPlayer::snapshot`] and continues where it was,
+    /// the playlist goes on from there. Returns `false` if no playlist track has the snapshot's name.
+    pub fn restore(&mut self, snapshot: &MusicSnapshot) -> bool {
+        let name_matches = |index: &usize| {
+            self.playlist.get(*index).map(|track| track.name == snapshot.track).unwrap_or(false)
+        };
+        let index = match snapshot.playlist_index
+            .filter(name_matches)
+            .or_else(|| self.playlist.iter().position(|track| track.name == snapshot.track))
+        {
+            Some(index) => index,
+            None => return false
+        };
+        if let Some(cursor) = self.play_order.iter().position(|i| *i == index) {
+            self.cursor = cursor;
+        }
+        self.request(self.playlist[index].clone(), Some(index), MusicTransition::Immediate);
+        self.pending.as_mut().unwrap().start_frame = snapshot.position;
+        true
+    }
+    /// Like [`MusicPlayer::restore`], for a track started with [`MusicPlayer::switch_to`]
+    pub fn restore_track(&mut self, snapshot: &MusicSnapshot, track: MusicTrack) {
+        self.request(track, None, MusicTransition::Immediate);
+        self.pending.as_mut().unwrap().start_frame = snapshot.position;
+    }
+
+    /// Starts the playlist from its current position
+    pub fn play(&mut self, transition: MusicTransition) {
+        if let Some(index) = self.play_order.get(self.cursor).copied() {
+            self.request(self.playlist[index].clone(), Some(index), transition);
+        }
+    }
+    pub fn next(&mut self, transition: MusicTransition) {
+        if self.advance_cursor(1) {
+            self.play(transition);
+        }
+    }
+    pub fn previous(&mut self, transition: MusicTransition) {
+        if self.advance_cursor(-1) {
+            self.play(transition);
+        }
+    }
+    /// Interrupts the playlist with a track which loops until something else is requested,
+    /// e.g. battle music when combat starts. Use [`MusicPlayer::play`] to get back to the playlist.
+    pub fn switch_to(&mut self, track: MusicTrack, transition: MusicTransition) {
+        self.request(track, None, transition);
+    }
+    /// Fades out whatever is playing
+    pub fn stop(&mut self, transition: MusicTransition) {
+        self.pending = None;
+        if let Some(mut current) = self.current.take() {
+            current.fade_direction = -1.0;
+            if transition == MusicTransition::Immediate {
+                current.fade = 0.0;
+            }
+            self.fading_out.push(current);
+        }
+    }
+
+    pub fn frame(&mut self, mixer: &mut SoundMixer, delta_time: f32) {
+        self.process_pending(mixer);
+        self.process_playlist();
+
+        let fade_step = if self.crossfade_ms == 0 {
+            1.0
+        } else {
+            delta_time / self.crossfade_ms as f32
+        };
+        let volume = self.volume.0;
+        if let Some(current) = &mut self.current {
+            current.fade = (current.fade + fade_step * current.fade_direction).min(1.0);
+            current.apply_volume(mixer, volume);
+        }
+        for track in self.fading_out.iter_mut() {
+            track.fade = (track.fade + fade_step * track.fade_direction).max(0.0);
+            if track.fade <= 0.0 {
+                mixer.stop(track.sound_id);
+            } else {
+                track.apply_volume(mixer, volume);
+            }
+        }
+        self.fading_out.retain(|track| track.fade > 0.0);
+    }
+
+    fn rebuild_play_order(&mut self) {
+        let current = self.play_order.get(self.cursor).copied();
+        self.play_order = (0..self.playlist.len()).collect();
+        if self.shuffle {
+            self.rng.shuffle(&mut self.play_order);
+        }
+        self.cursor = current
+            .and_then(|index| self.play_order.iter().position(|i| *i == index))
+            .unwrap_or(0);
+    }
+
+    fn advance_cursor(&mut self, delta: isize) -> bool {
+        if self.play_order.is_empty() {
+            return false;
+        }
+        let next = self.cursor as isize + delta;
+        if next >= 0 && (next as usize) < self.play_order.len() {
+            self.cursor = next as usize;
+            return true;
+        }
+        if self.repeat == RepeatMode::Off {
+            return false;
+        }
+        if next >= 0 && self.shuffle {
+            self.rng.shuffle(&mut self.play_order);
+        }
+        self.cursor = next.rem_euclid(self.play_order.len() as isize) as usize;
+        true
+    }
+
+    fn request(&mut self, track: MusicTrack, playlist_index: Option<usize>, transition: MusicTransition) {
+        let loops_at_request = self.current.as_ref().map(|c| c.progress.loops()).unwrap_or(0);
+        self.pending = Some(PendingSwitch {
+            track,
+            playlist_index,
+            transition,
+            loops_at_request,
+            start_frame: 0
+        });
+    }
+
+    fn process_pending(&mut self, mixer: &mut SoundMixer) {
+        let ready = match (&self.pending, &self.current) {
+            (None, _) => false,
+            (Some(pending), Some(current)) if pending.transition == MusicTransition::AtLoopBoundary => {
+                current.progress.loops() > pending.loops_at_request
+            }
+            _ => true
+        };
+        if !ready {
+            return;
+        }
+        let pending = self.pending.take().unwrap();
+        let immediate = pending.transition == MusicTransition::Immediate || self.crossfade_ms == 0;
+
+        if let Some(mut previous) = self.current.take() {
+            previous.fade_direction = -1.0;
+            if immediate {
+                previous.fade = 0.0;
+            }
+            self.fading_out.push(previous);
+        }
+
+        let initial_fade = if immediate { 1.0 } else { 0.0 };
+        let volume = Volume(self.volume.0 * initial_fade);
+        let PendingSwitch { track: MusicTrack { name, content }, playlist_index, start_frame, .. } = pending;
+        let (sound_id, progress) = match content {
+            TrackContent::Sampled(sound) => {
+                let length = sound.samples.len() / sound.channels.max(1) as usize;
+                let start_frame = start_frame % length.max(1);
+                let frames = Arc::new(AtomicUsize::new(start_frame));
+                let progress = TrackProgress::Sampled {
+                    frames: frames.clone(),
+                    length,
+                    sample_rate: sound.sample_rate
+                };
+                let mut inner = SampleSource::new(Sound {
+                    playback_style: PlaybackStyle::Looped,
+                    ..sound
+                });
+                inner.seek(start_frame);
+                let source = FrameCountingSource { inner, frames };
+                (mixer.play(PlaybackBuilder::new().with_source(source).with_volume(volume)), progress)
+            }
+            TrackContent::Tracker(module) => {
+                let mut player = ModulePlayer::new(module).with_looping(true);
+                if start_frame > 0 {
+                    player.seek(start_frame);
+                }
+                let progress = TrackProgress::Tracker(player.position());
+                (mixer.play(PlaybackBuilder::new().with_source(player).with_volume(volume)), progress)
+            }
+        };
+        self.current = sound_id.map(|sound_id| ActiveTrack {
+            name,
+            playlist_index,
+            sound_id,
+            progress,
+            fade: initial_fade,
+            fade_direction: 1.0,
+            applied_volume: volume.0
+        });
+    }
+
+    /// moves on to the next playlist entry when the current one is about to end
+    fn process_playlist(&mut self) {
+        if self.pending.is_some() || self.repeat == RepeatMode::One {
+            return;
+        }
+        let current = match &self.current {
+            Some(current) if current.playlist_index.is_some() => current,
+            _ => return
+        };
+        if !current.progress.is_finishing(self.crossfade_ms as f32) {
+            return;
+        }
+        if self.advance_cursor(1) {
+            self.play(MusicTransition::Crossfade);
+        } else {
+            self.stop(MusicTransition::Crossfade);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Render-ahead queue and latency, see [`crate::audio::SoundMixer::with_config`].
+use crate::audio::mixer::Volume;
+use crate::audio::volume::VolumeError;
+
+/// Length of a queue the mixer renders into ahead of the output device.
+///
+/// This is not the device buffer size: cpal 0.11 has no way to request one, the device always
+/// uses the buffer its backend picks. The queue sits in front of that buffer, so it can only add
+/// latency, never reduce it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderAhead {
+    /// no queue, render straight into the buffers the device asks for, lowest latency
+    Off,
+    /// render ahead on a separate thread, the queue holds this many frames
+    Frames(usize),
+    /// like [`RenderAhead::Frames`], converted at the device's sample rate
+    Milliseconds(f32)
+}
+impl RenderAhead {
+    pub(crate) fn frames(self, sample_rate: f32) -> usize {
+        match self {
+            RenderAhead::Off => 0,
+            RenderAhead::Frames(frames) => frames,
+            RenderAhead::Milliseconds(ms) => (ms.max(0.0) * 0.001 * sample_rate) as usize
+        }
+    }
+}
+
+/// Settings for creating a [`crate::audio::SoundMixer`].
+///
+/// A render-ahead queue adds its length to the latency, in exchange the mixer can miss
+/// a device callback deadline on a slow machine without the player hearing a dropout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioConfig {
+    pub(crate) initial_volume: Volume,
+    pub(crate) render_ahead: RenderAhead
+}
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl AudioConfig {
+    pub fn new() -> Self {
+        Self {
+            initial_volume: Volume(1.0),
+            render_ahead: RenderAhead::Off
+        }
+    }
+    pub fn with_volume(self, initial_volume: Volume) -> Result<Self, VolumeError> {
+        Ok(Self {
+            initial_volume: initial_volume.validate()?,
+            ..self
+        })
+    }
+    /// Queue in front of the device buffer, see [`RenderAhead`]
+    pub fn with_render_ahead(self, render_ahead: RenderAhead) -> Self {
+        Self {
+            render_ahead,
+            ..self
+        }
+    }
+    /// Shorthand for a render-ahead queue of `ms` milliseconds
+    pub fn with_render_ahead_ms(self, ms: f32) -> Self {
+        self.with_render_ahead(RenderAhead::Milliseconds(ms))
+    }
+}
+
+/// Effective output settings, see [`crate::audio::SoundMixer::output_info`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputInfo {
+    pub sample_rate: f32,
+    /// size of the last buffer the device asked for, zero until the first one
+    pub device_buffer_frames: u64,
+    /// length of the render-ahead queue, zero with [`RenderAhead::Off`]
+    pub queue_frames: u64,
+    /// device buffers which could only be partly filled because the queue ran dry
+    pub underruns: u64
+}
+impl OutputInfo {
+    /// worst case time between mixing a frame and the device playing it,
+    /// the device buffer is only known once the device asked for the first one
+    pub fn latency_frames(&self) -> u64 {
+        self.device_buffer_frames + self.queue_frames
+    }
+    pub fn latency_seconds(&self) -> f32 {
+        self.latency_frames() as f32 / self.sample_rate
+    }
+}
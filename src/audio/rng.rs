@@ -0,0 +1,43 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// tiny xorshift generator, good enough for noise and variation picking
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct XorShift32 {
+    state: u32
+}
+impl XorShift32 {
+    pub(crate) fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 { 0x9E37_79B9 } else { seed }
+        }
+    }
+    pub(crate) fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+            .unwrap_or(0);
+        Self::new(nanos)
+    }
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+    /// uniform value in `0.0..1.0`
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+    /// uniform value in `0..upper`, `upper` must be positive
+    pub(crate) fn next_below(&mut self, upper: usize) -> usize {
+        (self.next_u32() as usize) % upper
+    }
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.next_below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::convert::TryFrom;
+use std::io::Write;
+use crate::audio::byte_reader::{ByteReader, invalid_data};
+
+const MAGIC: &[u8] = b"RMSS";
+const VERSION: u8 = 1;
+
+/// State of one voice in a [`MixerSnapshot`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoiceSnapshot {
+    /// name of the [`crate::audio::SoundBank`] asset the voice plays
+    pub asset: String,
+    /// position in frames of the asset's sample rate
+    pub position: usize,
+    /// linear gain
+    pub gain: f32,
+    /// playback speed, e.g. picked by a [`crate::audio::SoundContainer`]
+    pub pitch: f32,
+    pub looping: bool,
+    pub paused: bool,
+    /// name of the bus given to [`crate::audio::SoundMixer::add_bus`]
+    pub bus: String,
+    pub tag: Option<String>
+}
+
+/// Track of a [`crate::audio::MusicPlayer`] captured with [`crate::audio::MusicPlayer::snapshot`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MusicSnapshot {
+    /// name of the [`crate::audio::MusicTrack`]
+    pub track: String,
+    /// `None` for tracks started with [`crate::audio::MusicPlayer::switch_to`]
+    pub playlist_index: Option<usize>,
+    /// frames into the track
+    pub position: usize
+}
+
+/// Voices of a [`crate::audio::SoundMixer`] captured for a savegame.
+///
+/// Only voices played from a [`crate::audio::SoundBank`] asset can be captured,
+/// procedural and streamed voices are left out. Music is driven by a
+/// [`crate::audio::MusicPlayer`] rather than the mixer, store its snapshot in `music`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MixerSnapshot {
+    pub voices: Vec<VoiceSnapshot>,
+    pub music: Option<MusicSnapshot>
+}
+
+fn write_text<W: Write>(writer: &mut W, text: &str) -> std::io::Result<()> {
+    let bytes = text.as_bytes();
+    if bytes.len() > u16::MAX as usize {
+        return Err(invalid_data("snapshot text is too long"));
+    }
+    writer.write_all(&(bytes.len() as u16).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_text(reader: &mut ByteReader) -> std::io::Result<String> {
+    let length = reader.u16_le()? as usize;
+    String::from_utf8(reader.bytes(length)?.to_vec())
+        .map_err(|_| invalid_data("snapshot text is not valid UTF-8"))
+}
+
+fn read_position(reader: &mut ByteReader) -> std::io::Result<usize> {
+    usize::try_from(reader.u64_le()?)
+        .map_err(|_| invalid_data("snapshot position does not fit in memory"))
+}
+
+impl MixerSnapshot {
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(self.voices.len() as u32).to_le_bytes())?;
+        for voice in self.voices.iter() {
+            write_text(writer, &voice.asset)?;
+            writer.write_all(&(voice.position as u64).to_le_bytes())?;
+            writer.write_all(&voice.gain.to_le_bytes())?;
+            writer.write_all(&voice.pitch.to_le_bytes())?;
+            let flags = voice.looping as u8 | (voice.paused as u8) << 1 | (voice.tag.is_some() as u8) << 2;
+            writer.write_all(&[flags])?;
+            write_text(writer, &voice.bus)?;
+            if let Some(tag) = &voice.tag {
+                write_text(writer, tag)?;
+            }
+        }
+        match &self.music {
+            Some(music) => {
+                writer.write_all(&[1])?;
+                write_text(writer, &music.track)?;
+                let playlist_index = music.playlist_index.map(|index| index as u32).unwrap_or(u32::MAX);
+                writer.write_all(&playlist_index.to_le_bytes())?;
+                writer.write_all(&(music.position as u64).to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?
+        }
+        Ok(())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a mixer snapshot"));
+        }
+        if reader.u8()? != VERSION {
+            return Err(invalid_data("unsupported mixer snapshot version"));
+        }
+        let count = reader.u32_le()? as usize;
+        let mut voices = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            let asset = read_text(&mut reader)?;
+            let position = read_position(&mut reader)?;
+            let gain = f32::from_bits(reader.u32_le()?);
+            let pitch = f32::from_bits(reader.u32_le()?);
+            let flags = reader.u8()?;
+            let bus = read_text(&mut reader)?;
+            let tag = if flags & 4 != 0 { Some(read_text(&mut reader)?) } else { None };
+            voices.push(VoiceSnapshot {
+                asset,
+                position,
+                gain,
+                pitch,
+                looping: flags & 1 != 0,
+                paused: flags & 2 != 0,
+                bus,
+                tag
+            });
+        }
+        let music = if reader.u8()? != 0 {
+            let track = read_text(&mut reader)?;
+            let playlist_index = match reader.u32_le()? {
+                u32::MAX => None,
+                index => Some(index as usize)
+            };
+            let position = read_position(&mut reader)?;
+            Some(MusicSnapshot { track, playlist_index, position })
+        } else {
+            None
+        };
+        Ok(Self { voices, music })
+    }
+}
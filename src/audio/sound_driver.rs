@@ -1,10 +1,147 @@
-use std::sync::mpsc::{Sender, channel};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::mpsc::{Sender, Receiver, TryRecvError, channel};
+use std::time::Duration;
 use super::SoundError;
 use super::mixer::MixerMessage;
 use cpal::traits::{HostTrait, DeviceTrait, EventLoopTrait};
 use cpal::{SampleRate, SampleFormat};
 use std::thread;
-use crate::audio::mixer::{ MixerInternal};
+use crate::audio::mixer::{MixerInternal, MixerShared};
+use crate::audio::output::RenderAhead;
+
+/// frames the render thread mixes at once when rendering ahead
+const RENDER_BLOCK_FRAMES: usize = 256;
+
+/// Single producer, single consumer queue of interleaved samples between the render thread and the device callback.
+/// Samples go in and come out in whole frames, so an underrun can't leave the channels swapped.
+struct SampleRing {
+    samples: Vec<AtomicU32>,
+    /// both only ever grow, their difference is the fill level
+    read: AtomicUsize,
+    write: AtomicUsize
+}
+impl SampleRing {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0)
+        }
+    }
+    fn free(&self) -> usize {
+        let filled = self.write.load(Ordering::Acquire).wrapping_sub(self.read.load(Ordering::Acquire));
+        self.samples.len() - filled
+    }
+    /// only called by the render thread, after checking [`SampleRing::free`].
+    /// The samples become visible to [`SampleRing::pop_frame`] all at once.
+    fn push_frames(&self, values: &[f32]) {
+        let write = self.write.load(Ordering::Relaxed);
+        for (i, value) in values.iter().enumerate() {
+            self.samples[write.wrapping_add(i) % self.samples.len()].store(value.to_bits(), Ordering::Relaxed);
+        }
+        self.write.store(write.wrapping_add(values.len()), Ordering::Release);
+    }
+    /// only called by the device callback, fills all of `frame` or leaves it untouched and returns false
+    fn pop_frame(&self, frame: &mut [f32]) -> bool {
+        let read = self.read.load(Ordering::Relaxed);
+        if self.write.load(Ordering::Acquire).wrapping_sub(read) < frame.len() {
+            return false;
+        }
+        for (i, value) in frame.iter_mut().enumerate() {
+            *value = f32::from_bits(self.samples[read.wrapping_add(i) % self.samples.len()].load(Ordering::Relaxed));
+        }
+        self.read.store(read.wrapping_add(frame.len()), Ordering::Release);
+        true
+    }
+}
+
+/// fills the device buffers, either by mixing right away or from the render-ahead queue
+enum SampleProducer {
+    Direct(Box<MixerInternal>, Receiver<MixerMessage>),
+    Queued {
+        ring: Arc<SampleRing>,
+        shared: Arc<MixerShared>,
+        /// the device takes samples one by one, the queue hands out whole frames
+        frame: Vec<f32>,
+        next_sample: usize,
+        underrun: bool
+    }
+}
+impl SampleProducer {
+    fn begin_buffer(&mut self) {
+        match self {
+            SampleProducer::Direct(generator, message_receiver) => {
+                for event in message_receiver.try_iter() {
+                    generator.handle_event(event);
+                }
+            }
+            SampleProducer::Queued { underrun, .. } => *underrun = false
+        }
+    }
+    fn next_value(&mut self) -> f32 {
+        match self {
+            SampleProducer::Direct(generator, _) => generator.next_value(),
+            SampleProducer::Queued { ring, frame, next_sample, underrun, .. } => {
+                if *next_sample == frame.len() {
+                    if !ring.pop_frame(frame) {
+                        frame.iter_mut().for_each(|value| *value = 0.0);
+                        *underrun = true;
+                    }
+                    *next_sample = 0;
+                }
+                *next_sample += 1;
+                frame[*next_sample - 1]
+            }
+        }
+    }
+    fn buffer_delivered(&mut self, frames: usize) {
+        match self {
+            SampleProducer::Direct(generator, _) => generator.buffer_delivered(frames),
+            SampleProducer::Queued { shared, underrun, .. } => {
+                shared.buffer_delivered(frames);
+                if *underrun {
+                    shared.underruns.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// Mixes `frames` frames into the render-ahead queue, as many as fit
+fn fill_queue(generator: &mut MixerInternal, ring: &SampleRing, channels: usize, frames: usize) -> usize {
+    let frames = frames.min(ring.free() / channels);
+    let samples: Vec<f32> = (0..frames * channels).map(|_| generator.next_value()).collect();
+    ring.push_frames(&samples);
+    generator.flush_captures();
+    frames
+}
+
+/// keeps the render-ahead queue full until the mixer is dropped
+fn render_ahead(
+    mut generator: Box<MixerInternal>,
+    message_receiver: Receiver<MixerMessage>,
+    ring: Arc<SampleRing>,
+    channels: usize,
+    sample_rate: f32
+) {
+    let block = RENDER_BLOCK_FRAMES.min(ring.samples.len() / channels).max(1) * channels;
+    let nap = Duration::from_secs_f32(block as f32 / channels as f32 / sample_rate.max(1.0) / 2.0);
+    loop {
+        loop {
+            match message_receiver.try_recv() {
+                Ok(event) => generator.handle_event(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return
+            }
+        }
+        if ring.free() < block {
+            thread::sleep(nap);
+            continue;
+        }
+        fill_queue(&mut generator, &ring, channels, block / channels);
+    }
+}
 
 pub struct SoundDriver {
     event_loop: Option<cpal::EventLoop>,
@@ -12,6 +149,11 @@ pub struct SoundDriver {
     stream_id: Option<cpal::StreamId>,
     message_transmitter: Option<Sender<MixerMessage>>,
     mixer: Option<Box<MixerInternal>>,
+    render_ahead: RenderAhead,
+    /// no device, the mixer stays here and handles messages right away
+    offline: bool,
+    /// render-ahead queue of an offline driver, filled by [`SoundDriver::render_ahead`]
+    offline_queue: Option<Arc<SampleRing>>,
     err: SoundError,
 }
 
@@ -22,7 +164,7 @@ impl SoundDriver {
     }
 
     /// Initialize the sound device and provide the generator to the driver.
-    pub(crate) fn new(generator: Box<MixerInternal>) -> Self {
+    pub(crate) fn new(generator: Box<MixerInternal>, render_ahead: RenderAhead) -> Self {
         // Setup the audio system
         let host = cpal::default_host();
         let event_loop = host.event_loop();
@@ -37,6 +179,9 @@ impl SoundDriver {
                     stream_id: None,
                     message_transmitter: None,
                     mixer: Some(generator),
+                    render_ahead,
+                    offline: false,
+                    offline_queue: None,
                     err: SoundError::NoDevice,
                 };
             }
@@ -52,6 +197,9 @@ impl SoundDriver {
                     stream_id: None,
                     message_transmitter: None,
                     mixer: Some(generator),
+                    render_ahead,
+                    offline: false,
+                    offline_queue: None,
                     err: SoundError::UnknownStreamFormat,
                 };
             }
@@ -92,6 +240,9 @@ impl SoundDriver {
                     stream_id: None,
                     message_transmitter: None,
                     mixer: Some(generator),
+                    render_ahead,
+                    offline: false,
+                    offline_queue: None,
                     err: SoundError::OutputStream,
                 };
             }
@@ -103,14 +254,81 @@ impl SoundDriver {
             stream_id: Some(stream_id),
             message_transmitter: None,
             mixer: Some(generator),
+            render_ahead,
+            offline: false,
+            offline_queue: None,
+            err: SoundError::NoError,
+        }
+    }
+
+    /// Driver without a device, the generator only advances in [`SoundDriver::render`]
+    /// or, with a render-ahead queue, in [`SoundDriver::render_ahead`]
+    pub(crate) fn offline(mut generator: Box<MixerInternal>, sample_rate: f32, render_ahead: RenderAhead) -> Self {
+        generator.init(sample_rate);
+        let queue_frames = render_ahead.frames(sample_rate);
+        let offline_queue = if queue_frames == 0 {
+            None
+        } else {
+            generator.shared().queue_frames.store(queue_frames as u64, Ordering::Relaxed);
+            Some(Arc::new(SampleRing::new(queue_frames * 2)))
+        };
+        Self {
+            event_loop: None,
+            format: None,
+            stream_id: None,
+            message_transmitter: None,
+            mixer: Some(generator),
+            render_ahead,
+            offline: true,
+            offline_queue,
             err: SoundError::NoError,
         }
     }
 
+    /// Pulls `frames` interleaved stereo frames out of an offline generator, or its queue when it has one
+    pub(crate) fn render(&mut self, frames: usize) -> Vec<f32> {
+        match (&mut self.mixer, &self.offline_queue) {
+            (Some(generator), None) if self.offline => {
+                let samples = (0..frames * 2).map(|_| generator.next_value()).collect();
+                generator.buffer_delivered(frames);
+                samples
+            }
+            (Some(generator), Some(ring)) => {
+                let mut samples = Vec::with_capacity(frames * 2);
+                let mut frame = [0.0; 2];
+                let mut underrun = false;
+                for _ in 0..frames {
+                    if !ring.pop_frame(&mut frame) {
+                        frame = [0.0; 2];
+                        underrun = true;
+                    }
+                    samples.extend_from_slice(&frame);
+                }
+                let shared = generator.shared();
+                shared.buffer_delivered(frames);
+                if underrun {
+                    shared.underruns.fetch_add(1, Ordering::Relaxed);
+                }
+                samples
+            }
+            _ => Vec::new()
+        }
+    }
+
+    /// Mixes up to `frames` frames into an offline driver's render-ahead queue, returns how many fit
+    pub(crate) fn render_ahead(&mut self, frames: usize) -> usize {
+        match (&mut self.mixer, &self.offline_queue) {
+            (Some(generator), Some(ring)) => fill_queue(generator, ring, 2, frames),
+            _ => 0
+        }
+    }
+
     /// Send an event to the generator
     pub(crate) fn send_event(&mut self, event: MixerMessage) {
         if let Some(ref mut tx) = self.message_transmitter {
             tx.send(event).unwrap();
+        } else if let (true, Some(generator)) = (self.offline, &mut self.mixer) {
+            generator.handle_event(event);
         }
     }
 
@@ -128,21 +346,34 @@ impl SoundDriver {
     /// On native target, it starts the sound thread and the audio loop.
     /// On web target, only the [`SoundDriver::frame`] function produces sound.
     pub fn start(&mut self) {
+        if self.offline {
+            return;
+        }
         let (tx, message_receiver) = channel();
         self.message_transmitter = Some(tx);
         let stream_id = self.stream_id.take().unwrap();
         let sample_rate = self.get_sample_rate();
+        let channels = self.format.as_ref().map(|fmt| fmt.channels as usize).unwrap_or(2).max(1);
         let mut generator = self.mixer.take().unwrap();
+        let queue_frames = self.render_ahead.frames(sample_rate);
         if let Some(evt) = self.event_loop.take() {
             evt.play_stream(stream_id).expect("could not play stream");
 
             thread::spawn(move || {
                 println!("starting audio loop");
                 generator.init(sample_rate);
+                let mut producer = if queue_frames == 0 {
+                    SampleProducer::Direct(generator, message_receiver)
+                } else {
+                    let ring = Arc::new(SampleRing::new(queue_frames * channels));
+                    let shared = generator.shared();
+                    shared.queue_frames.store(queue_frames as u64, Ordering::Relaxed);
+                    let render_ring = ring.clone();
+                    thread::spawn(move || render_ahead(generator, message_receiver, render_ring, channels, sample_rate));
+                    SampleProducer::Queued { ring, shared, frame: vec![0.0; channels], next_sample: channels, underrun: false }
+                };
                 evt.run(move |stream_id, stream_result| {
-                    for event in message_receiver.try_iter() {
-                        generator.handle_event(event);
-                    }
+                    producer.begin_buffer();
 
                     let stream_data = match stream_result {
                         Ok(data) => data,
@@ -152,32 +383,36 @@ impl SoundDriver {
                         }
                     };
 
-                    match stream_data {
+                    let buffer_len = match stream_data {
                         cpal::StreamData::Output {
                             buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer),
                         } => {
                             for elem in buffer.iter_mut() {
-                                *elem = ((generator.next_value() * 0.5 + 0.5)
+                                *elem = ((producer.next_value() * 0.5 + 0.5)
                                     * std::u16::MAX as f32)
                                     as u16;
                             }
+                            buffer.len()
                         }
                         cpal::StreamData::Output {
                             buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
                         } => {
                             for elem in buffer.iter_mut() {
-                                *elem = (generator.next_value() * std::i16::MAX as f32) as i16;
+                                *elem = (producer.next_value() * std::i16::MAX as f32) as i16;
                             }
+                            buffer.len()
                         }
                         cpal::StreamData::Output {
                             buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
                         } => {
                             for elem in buffer.iter_mut() {
-                                *elem = generator.next_value();
+                                *elem = producer.next_value();
                             }
+                            buffer.len()
                         }
                         _ => panic!("unsupported stream data"),
-                    }
+                    };
+                    producer.buffer_delivered(buffer_len / channels);
                 })
             });
         }
@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::audio::mixer::{Sound, PlaybackStyle};
+use crate::audio::rng::XorShift32;
+
+/// result of pulling a frame from a [`SoundSource`]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SourceState {
+    /// frame was written
+    Ready,
+    /// no data available right now, but more may arrive later
+    Starving,
+    /// source is exhausted and the voice can be dropped
+    Finished
+}
+
+/// Anything the audio thread can pull frames from.
+///
+/// Frames are interleaved, `frame.len()` always equals [`SoundSource::channels`],
+/// which must be either 1 or 2.
+pub trait SoundSource: Send {
+    fn sample_rate(&self) -> f32;
+    fn channels(&self) -> u16;
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState;
+    /// Receives content sent through [`crate::audio::SoundMixer::stream_sound`].
+    /// Sources which can't be fed simply drop it.
+    fn stream_content(&mut self, _content: Vec<f32>) {}
+    /// Current position in frames of the source's own sample rate, if it can tell
+    fn position(&self) -> Option<usize> {
+        None
+    }
+    /// Moves to a frame, returns `false` if the source can't seek
+    fn seek(&mut self, _frame: usize) -> bool {
+        false
+    }
+    fn is_looping(&self) -> bool {
+        false
+    }
+    /// Style of the samples played, `None` for sources which don't play a [`Sound`]
+    fn playback_style(&self) -> Option<PlaybackStyle> {
+        None
+    }
+    /// Playback speed relative to the source's content, see [`PitchedSource`]
+    fn pitch(&self) -> f32 {
+        1.0
+    }
+}
+
+/// [`SoundSource`] playing pre-decoded samples of a [`Sound`]
+pub struct SampleSource {
+    sound: Arc<Sound>,
+    playback_style: PlaybackStyle,
+    progress: usize
+}
+impl SampleSource {
+    pub fn new(sound: Sound) -> Self {
+        Self::shared(Arc::new(sound))
+    }
+    /// Plays a sound without copying its samples, e.g. one held by a [`crate::audio::SoundBank`]
+    pub fn shared(sound: Arc<Sound>) -> Self {
+        Self {
+            playback_style: sound.playback_style,
+            sound,
+            progress: 0
+        }
+    }
+    /// Overrides the playback style of the sound, e.g. to loop a shared one-shot
+    pub fn with_playback_style(self, playback_style: PlaybackStyle) -> Self {
+        Self {
+            playback_style,
+            ..self
+        }
+    }
+}
+impl From<Sound> for SampleSource {
+    fn from(sound: Sound) -> Self {
+        Self::new(sound)
+    }
+}
+impl SoundSource for SampleSource {
+    fn sample_rate(&self) -> f32 {
+        self.sound.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.sound.channels
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        let samples = &self.sound.samples;
+        if self.progress + frame.len() > samples.len() {
+            match self.playback_style {
+                PlaybackStyle::Once => return SourceState::Finished,
+                PlaybackStyle::Looped => {
+                    if samples.len() < frame.len() {
+                        return SourceState::Finished;
+                    }
+                    self.progress = 0;
+                }
+                PlaybackStyle::Streamed => return SourceState::Starving
+            }
+        }
+        frame.copy_from_slice(&samples[self.progress..self.progress + frame.len()]);
+        self.progress += frame.len();
+        SourceState::Ready
+    }
+
+    /// Content sent to a sound which isn't [`PlaybackStyle::Streamed`] is dropped
+    fn stream_content(&mut self, content: Vec<f32>) {
+        if self.playback_style != PlaybackStyle::Streamed {
+            return;
+        }
+        Arc::make_mut(&mut self.sound).samples.extend(content);
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.progress / self.sound.channels.max(1) as usize)
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        let channels = self.sound.channels.max(1) as usize;
+        let frames = self.sound.samples.len() / channels;
+        self.progress = frame.min(frames) * channels;
+        true
+    }
+
+    fn is_looping(&self) -> bool {
+        self.playback_style == PlaybackStyle::Looped
+    }
+
+    fn playback_style(&self) -> Option<PlaybackStyle> {
+        Some(self.playback_style)
+    }
+}
+
+/// [`SoundSource`] playing content as it arrives through [`crate::audio::SoundMixer::stream_sound`].
+///
+/// Unlike a streamed [`SampleSource`], played content is dropped, so a long stream
+/// only ever holds what was sent ahead of the playback position.
+pub struct StreamSource {
+    sample_rate: f32,
+    channels: u16,
+    queue: VecDeque<f32>,
+    /// frame of the stream the front of the queue belongs to
+    position: usize
+}
+impl StreamSource {
+    pub fn new(sample_rate: f32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            queue: VecDeque::new(),
+            position: 0
+        }
+    }
+    /// Frame of the stream the first content belongs to, e.g. when a stream is restarted
+    /// from the middle. [`SoundSource::position`] counts from the start of the stream.
+    pub fn with_position(self, position: usize) -> Self {
+        Self {
+            position,
+            ..self
+        }
+    }
+}
+impl SoundSource for StreamSource {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        let samples = frame.len();
+        if self.queue.len() < samples {
+            return SourceState::Starving;
+        }
+        for (sample, value) in frame.iter_mut().zip(self.queue.drain(..samples)) {
+            *sample = value;
+        }
+        self.position += 1;
+        SourceState::Ready
+    }
+
+    fn stream_content(&mut self, content: Vec<f32>) {
+        self.queue.extend(content);
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.position)
+    }
+
+    fn playback_style(&self) -> Option<PlaybackStyle> {
+        Some(PlaybackStyle::Streamed)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Noise
+}
+
+/// Attack-decay-sustain-release envelope. Times are in seconds, `sustain` is a level in `0.0..=1.0`
+#[derive(Clone, Copy, Debug)]
+pub struct Adsr {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32
+}
+impl Adsr {
+    /// envelope level at `t` seconds after note start, `gate` is a note length if any
+    fn level(&self, t: f64, gate: Option<f32>) -> Option<f32> {
+        let held_level = |t: f32| {
+            if t < self.attack {
+                t / self.attack
+            } else if t < self.attack + self.decay {
+                let alpha = (t - self.attack) / self.decay;
+                1.0 + (self.sustain - 1.0) * alpha
+            } else {
+                self.sustain
+            }
+        };
+        match gate {
+            Some(gate) if t >= gate as f64 => {
+                let released = (t - gate as f64) as f32;
+                if released >= self.release {
+                    None
+                } else {
+                    Some(held_level(gate) * (1.0 - released / self.release))
+                }
+            }
+            _ => Some(held_level(t as f32))
+        }
+    }
+}
+
+/// Plays another [`SoundSource`] faster or slower, changing its pitch along with the speed.
+/// Uses linear interpolation, which is fine for variation of sound effects.
+pub struct PitchedSource {
+    inner: Box<dyn SoundSource>,
+    ratio: f32,
+    fraction: f32,
+    current: [f32; 2],
+    next: [f32; 2]
+}
+impl PitchedSource {
+    /// `ratio` is the playback speed, 2.0 plays an octave higher
+    pub fn new(inner: impl SoundSource + 'static, ratio: f32) -> Self {
+        Self::boxed(Box::new(inner), ratio)
+    }
+    pub(crate) fn boxed(inner: Box<dyn SoundSource>, ratio: f32) -> Self {
+        Self {
+            inner,
+            ratio: ratio.max(0.01),
+            // primes `current` and `next` with the first two frames
+            fraction: 2.0,
+            current: [0.0; 2],
+            next: [0.0; 2]
+        }
+    }
+}
+impl SoundSource for PitchedSource {
+    fn sample_rate(&self) -> f32 {
+        self.inner.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        while self.fraction >= 1.0 {
+            let mut pulled = [0.0; 2];
+            match self.inner.next_frame(&mut pulled[..frame.len()]) {
+                SourceState::Ready => {
+                    self.current = self.next;
+                    self.next = pulled;
+                    self.fraction -= 1.0;
+                }
+                state => return state
+            }
+        }
+        for (i, value) in frame.iter_mut().enumerate() {
+            *value = self.current[i] + (self.next[i] - self.current[i]) * self.fraction;
+        }
+        self.fraction += self.ratio;
+        SourceState::Ready
+    }
+
+    fn stream_content(&mut self, content: Vec<f32>) {
+        self.inner.stream_content(content);
+    }
+
+    fn position(&self) -> Option<usize> {
+        // `current` and `next` are the two frames before the inner position,
+        // the next frame played lies `fraction` frames after `current`
+        self.inner.position()
+            .map(|position| (position + self.fraction as usize).saturating_sub(2))
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        if !self.inner.seek(frame) {
+            return false;
+        }
+        self.fraction = 2.0;
+        true
+    }
+
+    fn is_looping(&self) -> bool {
+        self.inner.is_looping()
+    }
+
+    fn playback_style(&self) -> Option<PlaybackStyle> {
+        self.inner.playback_style()
+    }
+
+    fn pitch(&self) -> f32 {
+        self.ratio * self.inner.pitch()
+    }
+}
+
+/// Procedural mono [`SoundSource`] producing a basic waveform
+pub struct Oscillator {
+    waveform: Waveform,
+    frequency: f32,
+    amplitude: f32,
+    sample_rate: f32,
+    envelope: Option<Adsr>,
+    duration: Option<f32>,
+    phase: f32,
+    /// frames played, an `f32` sum of seconds would stop growing after a few minutes
+    frames: u64,
+    rng: XorShift32
+}
+impl Oscillator {
+    pub fn new(waveform: Waveform, frequency: f32) -> Self {
+        Self {
+            waveform,
+            frequency,
+            amplitude: 1.0,
+            sample_rate: 44100.0,
+            envelope: None,
+            duration: None,
+            phase: 0.0,
+            frames: 0,
+            rng: XorShift32::new(0x1234_5678)
+        }
+    }
+    pub fn with_amplitude(self, amplitude: f32) -> Self {
+        Self {
+            amplitude,
+            ..self
+        }
+    }
+    pub fn with_sample_rate(self, sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            ..self
+        }
+    }
+    pub fn with_envelope(self, envelope: Adsr) -> Self {
+        Self {
+            envelope: Some(envelope),
+            ..self
+        }
+    }
+    /// Note length in seconds. After it passes, the envelope goes into release
+    /// (or the oscillator simply stops if it has no envelope).
+    /// Without a duration the oscillator plays until stopped.
+    pub fn with_duration(self, duration: f32) -> Self {
+        Self {
+            duration: Some(duration),
+            ..self
+        }
+    }
+    pub fn with_seed(self, seed: u32) -> Self {
+        Self {
+            rng: XorShift32::new(seed),
+            ..self
+        }
+    }
+}
+impl SoundSource for Oscillator {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        let elapsed = self.frames as f64 / self.sample_rate as f64;
+        let level = match (self.envelope, self.duration) {
+            (Some(envelope), duration) => envelope.level(elapsed, duration),
+            (None, Some(duration)) if elapsed >= duration as f64 => None,
+            (None, _) => Some(1.0)
+        };
+        let level = match level {
+            Some(level) => level,
+            None => return SourceState::Finished
+        };
+        let value = match self.waveform {
+            Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Saw => self.phase * 2.0 - 1.0,
+            Waveform::Noise => self.rng.next_f32() * 2.0 - 1.0
+        };
+        frame[0] = value * level * self.amplitude;
+
+        self.phase = (self.phase + self.frequency / self.sample_rate).fract();
+        self.frames += 1;
+        SourceState::Ready
+    }
+}
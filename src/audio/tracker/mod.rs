@@ -0,0 +1,223 @@
+//! Pure-Rust playback of tracker modules (MOD, S3M and XM).
+//!
+//! All three formats are parsed into one [`Module`] representation,
+//! which is then rendered by a [`ModulePlayer`] as a procedural voice of the [`crate::audio::SoundMixer`].
+use std::io::ErrorKind;
+
+mod protracker;
+mod s3m;
+mod xm;
+mod player;
+pub use player::{ModulePlayer, ModulePosition};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ModuleFormat {
+    Mod,
+    S3m,
+    Xm
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum FrequencyMode {
+    Amiga,
+    Linear
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Note {
+    None,
+    /// note index, 48 is C-4
+    On(u8),
+    /// key off, releases instrument envelopes
+    Off,
+    /// hard cut of the voice
+    Cut
+}
+
+/// Effect commands of all formats normalized to one set.
+/// A zero parameter means "reuse the last one" where the formats allow it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Effect {
+    None,
+    Arpeggio(u8),
+    PortaUp(u8),
+    PortaDown(u8),
+    FinePortaUp(u8),
+    FinePortaDown(u8),
+    ExtraFinePortaUp(u8),
+    ExtraFinePortaDown(u8),
+    TonePorta(u8),
+    Vibrato(u8),
+    TonePortaVolumeSlide(u8),
+    VibratoVolumeSlide(u8),
+    VolumeSlide(u8),
+    FineVolumeSlideUp(u8),
+    FineVolumeSlideDown(u8),
+    SetVolume(u8),
+    SetPanning(u8),
+    PanningSlide(u8),
+    SampleOffset(u8),
+    PositionJump(u8),
+    PatternBreak(u8),
+    PatternLoop(u8),
+    PatternDelay(u8),
+    NoteCut(u8),
+    NoteDelay(u8),
+    Retrigger(u8),
+    KeyOff(u8),
+    SetSpeed(u8),
+    SetTempo(u8),
+    SetGlobalVolume(u8),
+    GlobalVolumeSlide(u8)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Cell {
+    pub(crate) note: Note,
+    /// 1-based instrument number, 0 means none
+    pub(crate) instrument: u8,
+    pub(crate) volume: Option<u8>,
+    pub(crate) volume_effect: Effect,
+    pub(crate) effect: Effect
+}
+impl Cell {
+    pub(crate) const EMPTY: Cell = Cell {
+        note: Note::None,
+        instrument: 0,
+        volume: None,
+        volume_effect: Effect::None,
+        effect: Effect::None
+    };
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Pattern {
+    pub(crate) rows: usize,
+    /// row-major, `rows * channels` cells
+    pub(crate) cells: Vec<Cell>
+}
+impl Pattern {
+    pub(crate) fn empty(rows: usize, channels: usize) -> Self {
+        Self {
+            rows,
+            cells: vec![Cell::EMPTY; rows * channels]
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum LoopKind {
+    None,
+    Forward,
+    PingPong
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sample {
+    pub(crate) data: Vec<f32>,
+    pub(crate) loop_kind: LoopKind,
+    pub(crate) loop_start: usize,
+    pub(crate) loop_end: usize,
+    /// 0..=64
+    pub(crate) volume: u8,
+    /// playback rate of C-4
+    pub(crate) c4_speed: f32,
+    pub(crate) panning: Option<u8>
+}
+impl Sample {
+    pub(crate) fn c4_speed_from_tuning(relative_note: i8, finetune: i8) -> f32 {
+        8363.0 * 2f32.powf((relative_note as f32 + finetune as f32 / 128.0) / 12.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Envelope {
+    /// (tick, value 0..=64) pairs
+    pub(crate) points: Vec<(u16, u8)>,
+    pub(crate) sustain: Option<usize>,
+    pub(crate) repeat: Option<(usize, usize)>
+}
+impl Envelope {
+    pub(crate) fn value_at(&self, tick: u16) -> f32 {
+        let last = match self.points.last() {
+            Some(last) => last,
+            None => return 64.0
+        };
+        if tick >= last.0 {
+            return last.1 as f32;
+        }
+        for pair in self.points.windows(2) {
+            let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+            if tick >= t0 && tick < t1 {
+                let alpha = (tick - t0) as f32 / (t1 - t0) as f32;
+                return v0 as f32 + (v1 as f32 - v0 as f32) * alpha;
+            }
+        }
+        self.points[0].1 as f32
+    }
+    pub(crate) fn point_tick(&self, index: usize) -> Option<u16> {
+        self.points.get(index).map(|p| p.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Instrument {
+    pub(crate) samples: Vec<Sample>,
+    /// sample index for each of the 96 notes
+    pub(crate) keymap: [u8; 96],
+    pub(crate) volume_envelope: Option<Envelope>,
+    pub(crate) panning_envelope: Option<Envelope>,
+    pub(crate) fadeout: u16
+}
+impl Instrument {
+    pub(crate) fn from_sample(sample: Sample) -> Self {
+        Self {
+            samples: vec![sample],
+            keymap: [0; 96],
+            volume_envelope: None,
+            panning_envelope: None,
+            fadeout: 0
+        }
+    }
+    pub(crate) fn sample_for_note(&self, note: u8) -> Option<(usize, &Sample)> {
+        let index = self.keymap.get(note as usize).copied().unwrap_or(0) as usize;
+        self.samples.get(index).map(|sample| (index, sample))
+    }
+}
+
+/// Parsed tracker module
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub title: String,
+    pub format: ModuleFormat,
+    pub(crate) channels: usize,
+    pub(crate) orders: Vec<usize>,
+    pub(crate) restart_position: usize,
+    pub(crate) patterns: Vec<Pattern>,
+    pub(crate) instruments: Vec<Instrument>,
+    pub(crate) channel_panning: Vec<u8>,
+    pub(crate) initial_speed: u8,
+    pub(crate) initial_tempo: u8,
+    pub(crate) initial_global_volume: u8,
+    pub(crate) frequency_mode: FrequencyMode
+}
+impl Module {
+    /// Parses a module, detecting its format from the content
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        if xm::is_xm(bytes) {
+            xm::parse(bytes)
+        } else if s3m::is_s3m(bytes) {
+            s3m::parse(bytes)
+        } else if protracker::is_mod(bytes) {
+            protracker::parse(bytes)
+        } else {
+            Err(std::io::Error::new(ErrorKind::InvalidData, "unrecognized tracker module format"))
+        }
+    }
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+}
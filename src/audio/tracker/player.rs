@@ -0,0 +1,777 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::f32::consts::PI;
+use crate::audio::source::{SoundSource, SourceState};
+use super::{Module, FrequencyMode, Cell, Note, Effect, LoopKind, Envelope};
+
+const FADEOUT_MAX: i32 = 65536;
+
+#[derive(Default)]
+struct PositionShared {
+    order: AtomicUsize,
+    row: AtomicUsize,
+    loops: AtomicUsize,
+    frame: AtomicUsize,
+    finished: AtomicBool
+}
+
+/// Cloneable handle for querying where a [`ModulePlayer`] is,
+/// stays valid after the player was moved into the mixer
+#[derive(Clone, Default)]
+pub struct ModulePosition(Arc<PositionShared>);
+impl ModulePosition {
+    /// index into the order list
+    pub fn order(&self) -> usize {
+        self.0.order.load(Ordering::Relaxed)
+    }
+    pub fn row(&self) -> usize {
+        self.0.row.load(Ordering::Relaxed)
+    }
+    /// how many times the song wrapped around to its restart position
+    pub fn loops(&self) -> usize {
+        self.0.loops.load(Ordering::Relaxed)
+    }
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.load(Ordering::Relaxed)
+    }
+    /// frames into the song, see [`ModulePlayer`]'s [`SoundSource::position`]
+    pub fn frame(&self) -> usize {
+        self.0.frame.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+struct Channel {
+    /// 1-based, 0 means none
+    instrument: usize,
+    sample: Option<(usize, usize)>,
+    note: u8,
+    active: bool,
+    position: f64,
+    backwards: bool,
+    step: f64,
+    period: f32,
+    target_period: f32,
+    c4_speed: f32,
+    arpeggio_offset: f32,
+    vibrato_offset: f32,
+    volume: i32,
+    panning: i32,
+    key_on: bool,
+    fadeout: i32,
+    volume_envelope_tick: u16,
+    panning_envelope_tick: u16,
+    left_gain: f32,
+    right_gain: f32,
+    cell: Cell,
+    porta_memory: u8,
+    fine_porta_memory: u8,
+    tone_porta_speed: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_position: u8,
+    volume_slide_memory: u8,
+    fine_volume_slide_memory: u8,
+    panning_slide_memory: u8,
+    offset_memory: u8,
+    retrigger_ticks: u8,
+    loop_row: usize,
+    loop_count: u8
+}
+impl Channel {
+    fn new(panning: u8) -> Self {
+        Self {
+            instrument: 0,
+            sample: None,
+            note: 48,
+            active: false,
+            position: 0.0,
+            backwards: false,
+            step: 0.0,
+            period: 0.0,
+            target_period: 0.0,
+            c4_speed: 8363.0,
+            arpeggio_offset: 0.0,
+            vibrato_offset: 0.0,
+            volume: 0,
+            panning: panning as i32,
+            key_on: false,
+            fadeout: FADEOUT_MAX,
+            volume_envelope_tick: 0,
+            panning_envelope_tick: 0,
+            left_gain: 0.0,
+            right_gain: 0.0,
+            cell: Cell::EMPTY,
+            porta_memory: 0,
+            fine_porta_memory: 0,
+            tone_porta_speed: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_position: 0,
+            volume_slide_memory: 0,
+            fine_volume_slide_memory: 0,
+            panning_slide_memory: 0,
+            offset_memory: 0,
+            retrigger_ticks: 0,
+            loop_row: 0,
+            loop_count: 0
+        }
+    }
+
+    fn is_tone_porta(cell: &Cell) -> bool {
+        matches!(
+            (cell.effect, cell.volume_effect),
+            (Effect::TonePorta(_), _) | (Effect::TonePortaVolumeSlide(_), _) | (_, Effect::TonePorta(_))
+        )
+    }
+
+    fn restart_envelopes(&mut self) {
+        self.key_on = true;
+        self.fadeout = FADEOUT_MAX;
+        self.volume_envelope_tick = 0;
+        self.panning_envelope_tick = 0;
+    }
+
+    fn key_off(&mut self, module: &Module) {
+        self.key_on = false;
+        let has_envelope = module.instruments.get(self.instrument.wrapping_sub(1))
+            .map(|instrument| instrument.volume_envelope.is_some())
+            .unwrap_or(false);
+        if !has_envelope {
+            self.volume = 0;
+        }
+    }
+
+    fn trigger(&mut self, module: &Module, cell: &Cell) {
+        if cell.instrument != 0 {
+            self.instrument = cell.instrument as usize;
+            let note = match cell.note {
+                Note::On(note) => note,
+                _ => self.note
+            };
+            let sample = module.instruments
+                .get(self.instrument - 1)
+                .and_then(|instrument| instrument.sample_for_note(note));
+            if let Some((_, sample)) = sample {
+                self.volume = sample.volume as i32;
+                if let Some(panning) = sample.panning {
+                    self.panning = panning as i32;
+                }
+            }
+            self.restart_envelopes();
+        }
+
+        match cell.note {
+            Note::On(note) => {
+                let instrument = match module.instruments.get(self.instrument.wrapping_sub(1)) {
+                    Some(instrument) => instrument,
+                    None => return
+                };
+                let (sample_index, sample) = match instrument.sample_for_note(note) {
+                    Some(found) => found,
+                    None => return
+                };
+                let period = period_for(module.frequency_mode, note, sample.c4_speed);
+                if Self::is_tone_porta(cell) && self.active {
+                    self.target_period = period;
+                } else {
+                    self.sample = Some((self.instrument - 1, sample_index));
+                    self.note = note;
+                    self.c4_speed = sample.c4_speed;
+                    self.period = period;
+                    self.target_period = period;
+                    self.position = 0.0;
+                    self.backwards = false;
+                    self.active = true;
+                    self.vibrato_position = 0;
+                    if let Effect::SampleOffset(offset) = cell.effect {
+                        if offset != 0 {
+                            self.offset_memory = offset;
+                        }
+                        self.position = self.offset_memory as f64 * 256.0;
+                        if self.position >= sample.data.len() as f64 {
+                            self.active = false;
+                        }
+                    }
+                    self.restart_envelopes();
+                }
+            }
+            Note::Off => self.key_off(module),
+            Note::Cut => self.active = false,
+            Note::None => {}
+        }
+
+        if let Some(volume) = cell.volume {
+            self.volume = volume as i32;
+        }
+    }
+
+    fn volume_slide(&mut self, param: u8) {
+        if param != 0 {
+            self.volume_slide_memory = param;
+        }
+        let (x, y) = (self.volume_slide_memory >> 4, self.volume_slide_memory & 0x0F);
+        self.volume = if x > 0 { self.volume + x as i32 } else { self.volume - y as i32 }.clamp(0, 64);
+    }
+
+    fn tone_porta(&mut self, unit: f32) {
+        let speed = self.tone_porta_speed as f32 * unit;
+        if self.period < self.target_period {
+            self.period = (self.period + speed).min(self.target_period);
+        } else if self.period > self.target_period {
+            self.period = (self.period - speed).max(self.target_period);
+        }
+    }
+
+    fn vibrato(&mut self, unit: f32) {
+        let phase = self.vibrato_position as f32 / 64.0 * 2.0 * PI;
+        self.vibrato_offset = phase.sin() * 2.0 * self.vibrato_depth as f32 * unit;
+        self.vibrato_position = (self.vibrato_position + self.vibrato_speed) % 64;
+    }
+
+    /// effects taking place on the first tick of a row
+    fn first_tick(&mut self, effect: Effect, unit: f32, module: &Module) {
+        match effect {
+            Effect::SetVolume(volume) => self.volume = volume as i32,
+            Effect::SetPanning(panning) => self.panning = panning as i32,
+            Effect::PortaUp(param) | Effect::PortaDown(param) if param != 0 => self.porta_memory = param,
+            Effect::FinePortaUp(param) | Effect::FinePortaDown(param) |
+            Effect::ExtraFinePortaUp(param) | Effect::ExtraFinePortaDown(param) => {
+                if param != 0 {
+                    self.fine_porta_memory = param;
+                }
+                let amount = self.fine_porta_memory as f32 * unit;
+                self.period += match effect {
+                    Effect::FinePortaUp(_) => -amount,
+                    Effect::FinePortaDown(_) => amount,
+                    Effect::ExtraFinePortaUp(_) => -amount / 4.0,
+                    _ => amount / 4.0
+                };
+            }
+            Effect::TonePorta(param) if param != 0 => self.tone_porta_speed = param,
+            Effect::Vibrato(param) => {
+                if param >> 4 != 0 {
+                    self.vibrato_speed = param >> 4;
+                }
+                if param & 0x0F != 0 {
+                    self.vibrato_depth = param & 0x0F;
+                }
+            }
+            Effect::VolumeSlide(param) | Effect::TonePortaVolumeSlide(param) |
+            Effect::VibratoVolumeSlide(param) if param != 0 => self.volume_slide_memory = param,
+            Effect::FineVolumeSlideUp(param) | Effect::FineVolumeSlideDown(param) => {
+                if param != 0 {
+                    self.fine_volume_slide_memory = param;
+                }
+                let amount = self.fine_volume_slide_memory as i32;
+                self.volume = match effect {
+                    Effect::FineVolumeSlideUp(_) => self.volume + amount,
+                    _ => self.volume - amount
+                }.clamp(0, 64);
+            }
+            Effect::PanningSlide(param) if param != 0 => self.panning_slide_memory = param,
+            Effect::Retrigger(_) => self.retrigger_ticks = 0,
+            Effect::NoteCut(0) => self.volume = 0,
+            Effect::KeyOff(0) => self.key_off(module),
+            _ => {}
+        }
+    }
+
+    /// effects taking place on every tick but the first one
+    fn other_tick(&mut self, effect: Effect, tick: u32, unit: f32, module: &Module) {
+        match effect {
+            Effect::Arpeggio(param) => {
+                self.arpeggio_offset = match tick % 3 {
+                    0 => 0.0,
+                    1 => (param >> 4) as f32,
+                    _ => (param & 0x0F) as f32
+                };
+            }
+            Effect::PortaUp(_) => self.period -= self.porta_memory as f32 * unit,
+            Effect::PortaDown(_) => self.period += self.porta_memory as f32 * unit,
+            Effect::TonePorta(_) => self.tone_porta(unit),
+            Effect::Vibrato(_) => self.vibrato(unit),
+            Effect::TonePortaVolumeSlide(param) => {
+                self.tone_porta(unit);
+                self.volume_slide(param);
+            }
+            Effect::VibratoVolumeSlide(param) => {
+                self.vibrato(unit);
+                self.volume_slide(param);
+            }
+            Effect::VolumeSlide(param) => self.volume_slide(param),
+            Effect::PanningSlide(_) => {
+                let (x, y) = (self.panning_slide_memory >> 4, self.panning_slide_memory & 0x0F);
+                self.panning = if x > 0 { self.panning + x as i32 } else { self.panning - y as i32 }.clamp(0, 255);
+            }
+            Effect::Retrigger(interval) if interval != 0 => {
+                self.retrigger_ticks += 1;
+                if self.retrigger_ticks >= interval {
+                    self.retrigger_ticks = 0;
+                    self.position = 0.0;
+                    self.backwards = false;
+                }
+            }
+            Effect::NoteCut(at) if at as u32 == tick => self.volume = 0,
+            Effect::KeyOff(at) if at as u32 == tick => self.key_off(module),
+            _ => {}
+        }
+    }
+
+    fn update_envelopes(&mut self, module: &Module) {
+        let instrument = match module.instruments.get(self.instrument.wrapping_sub(1)) {
+            Some(instrument) => instrument,
+            None => return
+        };
+        if let Some(envelope) = &instrument.volume_envelope {
+            self.volume_envelope_tick = advance_envelope(envelope, self.volume_envelope_tick, self.key_on);
+            if !self.key_on {
+                self.fadeout = (self.fadeout - instrument.fadeout as i32).max(0);
+            }
+        }
+        if let Some(envelope) = &instrument.panning_envelope {
+            self.panning_envelope_tick = advance_envelope(envelope, self.panning_envelope_tick, self.key_on);
+        }
+    }
+
+    /// recalculates playback step and gains once per tick
+    fn update_mix(&mut self, module: &Module, sample_rate: f32, global_volume: i32, master_gain: f32) {
+        let instrument = module.instruments.get(self.instrument.wrapping_sub(1));
+        let mut volume = self.volume as f32 / 64.0 * global_volume as f32 / 64.0 * master_gain;
+        let mut panning = self.panning as f32;
+        if let Some(instrument) = instrument {
+            if let Some(envelope) = &instrument.volume_envelope {
+                volume *= envelope.value_at(self.volume_envelope_tick) / 64.0;
+                volume *= self.fadeout as f32 / FADEOUT_MAX as f32;
+            }
+            if let Some(envelope) = &instrument.panning_envelope {
+                let offset = envelope.value_at(self.panning_envelope_tick) - 32.0;
+                panning += offset * (128.0 - (panning - 128.0).abs()) / 32.0;
+            }
+        }
+        let panning = (panning / 255.0).clamp(0.0, 1.0);
+        self.left_gain = volume * (1.0 - panning).sqrt();
+        self.right_gain = volume * panning.sqrt();
+
+        self.period = clamp_period(module.frequency_mode, self.period);
+        let period = self.period + self.vibrato_offset;
+        let frequency = match module.frequency_mode {
+            FrequencyMode::Amiga => 8363.0 * 428.0 / period.max(1.0),
+            FrequencyMode::Linear => self.c4_speed * 2f32.powf(-period / 768.0)
+        } * 2f32.powf(self.arpeggio_offset / 12.0);
+        self.step = (frequency / sample_rate) as f64;
+    }
+}
+
+fn period_for(mode: FrequencyMode, note: u8, c4_speed: f32) -> f32 {
+    let semitones = note as f32 - 48.0;
+    match mode {
+        FrequencyMode::Amiga => 428.0 * 2f32.powf(-semitones / 12.0) * 8363.0 / c4_speed,
+        FrequencyMode::Linear => -semitones * 64.0
+    }
+}
+
+fn clamp_period(mode: FrequencyMode, period: f32) -> f32 {
+    match mode {
+        FrequencyMode::Amiga => period.clamp(14.0, 32000.0),
+        FrequencyMode::Linear => period.clamp(-64.0 * 72.0, 64.0 * 72.0)
+    }
+}
+
+fn advance_envelope(envelope: &Envelope, tick: u16, key_on: bool) -> u16 {
+    if key_on {
+        if let Some(sustain) = envelope.sustain {
+            if envelope.point_tick(sustain) == Some(tick) {
+                return tick;
+            }
+        }
+    }
+    if let Some((start, end)) = envelope.repeat {
+        if let (Some(start), Some(end)) = (envelope.point_tick(start), envelope.point_tick(end)) {
+            if tick >= end {
+                return start;
+            }
+        }
+    }
+    tick.saturating_add(1)
+}
+
+/// [`SoundSource`] rendering a [`Module`] as a stereo voice
+pub struct ModulePlayer {
+    module: Arc<Module>,
+    sample_rate: f32,
+    looping: bool,
+    channels: Vec<Channel>,
+    order: usize,
+    row: usize,
+    tick: u32,
+    speed: u8,
+    tempo: u8,
+    global_volume: i32,
+    pattern_delay: u8,
+    jump_order: Option<usize>,
+    jump_row: Option<usize>,
+    loop_jump: Option<usize>,
+    visited: Vec<bool>,
+    started: bool,
+    finished: bool,
+    frames_to_tick: usize,
+    master_gain: f32,
+    start_order: usize,
+    /// frames played since the start, wound back to `restart_frame` whenever the song loops
+    frames: usize,
+    /// frame the restart position was first reached at
+    restart_frame: Option<usize>,
+    position: ModulePosition
+}
+impl ModulePlayer {
+    pub fn new(module: Arc<Module>) -> Self {
+        let channels = module.channel_panning.iter().map(|p| Channel::new(*p)).collect();
+        let master_gain = 1.0 / (module.channels.max(4) as f32).sqrt();
+        Self {
+            sample_rate: 44100.0,
+            looping: false,
+            channels,
+            order: 0,
+            row: 0,
+            tick: 0,
+            speed: module.initial_speed,
+            tempo: module.initial_tempo,
+            global_volume: module.initial_global_volume as i32,
+            pattern_delay: 0,
+            jump_order: None,
+            jump_row: None,
+            loop_jump: None,
+            visited: vec![false; module.orders.len()],
+            started: false,
+            finished: module.orders.is_empty(),
+            frames_to_tick: 0,
+            master_gain,
+            start_order: 0,
+            frames: 0,
+            restart_frame: None,
+            position: ModulePosition::default(),
+            module
+        }
+    }
+    /// When enabled, the song starts over from its restart position instead of finishing
+    pub fn with_looping(self, looping: bool) -> Self {
+        Self {
+            looping,
+            ..self
+        }
+    }
+    pub fn with_sample_rate(self, sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            ..self
+        }
+    }
+    /// Starts playback from the given order instead of the first one
+    pub fn with_start_order(self, order: usize) -> Self {
+        let order = order.min(self.module.orders.len().saturating_sub(1));
+        Self {
+            order,
+            start_order: order,
+            ..self
+        }
+    }
+    pub fn position(&self) -> ModulePosition {
+        self.position.clone()
+    }
+
+    fn pattern_rows(&self, order: usize) -> usize {
+        self.module.orders.get(order)
+            .and_then(|pattern| self.module.patterns.get(*pattern))
+            .map(|pattern| pattern.rows)
+            .unwrap_or(64)
+    }
+
+    fn advance_row(&mut self) {
+        let (mut order, mut row, explicit) = if let Some(row) = self.loop_jump.take() {
+            (self.order, row, false)
+        } else if self.jump_order.is_some() || self.jump_row.is_some() {
+            let order = self.jump_order.take().unwrap_or(self.order + 1);
+            (order, self.jump_row.take().unwrap_or(0), true)
+        } else {
+            (self.order, self.row + 1, false)
+        };
+        if row >= self.pattern_rows(order) {
+            order += 1;
+            row = 0;
+        }
+        let mut explicit = explicit;
+        if order >= self.module.orders.len() {
+            order = self.module.restart_position;
+            explicit = true;
+        }
+        if order != self.order || explicit {
+            // a malformed module can point past its order list, treat that as its end
+            let visited = match self.visited.get(order) {
+                Some(visited) => *visited,
+                None => {
+                    self.finished = true;
+                    self.position.0.finished.store(true, Ordering::Relaxed);
+                    return;
+                }
+            };
+            if visited {
+                if !self.looping {
+                    self.finished = true;
+                    self.position.0.finished.store(true, Ordering::Relaxed);
+                    return;
+                }
+                self.position.0.loops.fetch_add(1, Ordering::Relaxed);
+                for visited in self.visited.iter_mut() {
+                    *visited = false;
+                }
+                // a position past the first pass maps back into it, so seeking there stays quick
+                if let (true, Some(restart_frame)) = (order == self.module.restart_position, self.restart_frame) {
+                    self.frames = restart_frame;
+                }
+            } else if order == self.module.restart_position && self.restart_frame.is_none() {
+                self.restart_frame = Some(self.frames);
+            }
+            self.visited[order] = true;
+            for channel in self.channels.iter_mut() {
+                channel.loop_row = 0;
+                channel.loop_count = 0;
+            }
+        }
+        self.order = order;
+        self.row = row;
+    }
+
+    fn process_row(&mut self) {
+        let module = self.module.clone();
+        let unit = unit_for(module.frequency_mode);
+        let pattern = module.orders.get(self.order).and_then(|p| module.patterns.get(*p));
+        self.pattern_delay = 0;
+        for index in 0..self.channels.len() {
+            let cell = pattern
+                .and_then(|pattern| pattern.cells.get(self.row * module.channels + index))
+                .copied()
+                .unwrap_or(Cell::EMPTY);
+
+            match cell.effect {
+                Effect::SetSpeed(speed) => self.speed = speed,
+                Effect::SetTempo(tempo) => self.tempo = tempo,
+                Effect::PositionJump(order) => self.jump_order = Some(order as usize),
+                Effect::PatternBreak(row) => self.jump_row = Some(row as usize),
+                Effect::PatternDelay(delay) => self.pattern_delay = delay,
+                Effect::SetGlobalVolume(volume) => self.global_volume = volume as i32,
+                Effect::PatternLoop(0) => self.channels[index].loop_row = self.row,
+                Effect::PatternLoop(count) => {
+                    let channel = &mut self.channels[index];
+                    if channel.loop_count == 0 {
+                        channel.loop_count = count;
+                        self.loop_jump = Some(channel.loop_row);
+                    } else {
+                        channel.loop_count -= 1;
+                        if channel.loop_count > 0 {
+                            self.loop_jump = Some(channel.loop_row);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            let channel = &mut self.channels[index];
+            channel.cell = cell;
+            channel.arpeggio_offset = 0.0;
+            channel.vibrato_offset = 0.0;
+            if let Effect::NoteDelay(delay) = cell.effect {
+                if delay != 0 {
+                    continue;
+                }
+            }
+            channel.trigger(&module, &cell);
+            channel.first_tick(cell.volume_effect, unit, &module);
+            channel.first_tick(cell.effect, unit, &module);
+        }
+        self.position.0.order.store(self.order, Ordering::Relaxed);
+        self.position.0.row.store(self.row, Ordering::Relaxed);
+    }
+
+    fn process_tick(&mut self) {
+        let module = self.module.clone();
+        if self.tick == 0 {
+            if self.started {
+                self.advance_row();
+                if self.finished {
+                    return;
+                }
+            } else {
+                self.started = true;
+                self.visited[self.order] = true;
+                if self.order == self.module.restart_position {
+                    self.restart_frame = Some(self.frames);
+                }
+            }
+            self.process_row();
+        } else {
+            let unit = unit_for(module.frequency_mode);
+            let tick = self.tick % self.speed.max(1) as u32;
+            for channel in self.channels.iter_mut().filter(|_| tick != 0) {
+                let cell = channel.cell;
+                if let Effect::NoteDelay(delay) = cell.effect {
+                    if delay as u32 == tick {
+                        channel.trigger(&module, &cell);
+                        channel.first_tick(cell.volume_effect, unit, &module);
+                    }
+                }
+                if let Effect::GlobalVolumeSlide(param) = cell.effect {
+                    let (x, y) = (param >> 4, param & 0x0F);
+                    self.global_volume = if x > 0 {
+                        self.global_volume + x as i32
+                    } else {
+                        self.global_volume - y as i32
+                    }.clamp(0, 64);
+                }
+                channel.other_tick(cell.volume_effect, tick, unit, &module);
+                channel.other_tick(cell.effect, tick, unit, &module);
+            }
+        }
+
+        for channel in self.channels.iter_mut() {
+            channel.update_envelopes(&module);
+            channel.update_mix(&module, self.sample_rate, self.global_volume, self.master_gain);
+        }
+
+        self.tick += 1;
+        if self.tick >= self.speed.max(1) as u32 * (1 + self.pattern_delay as u32) {
+            self.tick = 0;
+        }
+    }
+}
+
+fn unit_for(mode: FrequencyMode) -> f32 {
+    match mode {
+        FrequencyMode::Amiga => 1.0,
+        FrequencyMode::Linear => 4.0
+    }
+}
+
+impl SoundSource for ModulePlayer {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        if self.frames_to_tick == 0 {
+            if !self.finished {
+                self.process_tick();
+            }
+            if self.finished {
+                return SourceState::Finished;
+            }
+            self.frames_to_tick = ((self.sample_rate * 2.5 / self.tempo.max(1) as f32) as usize).max(1);
+        }
+        self.frames_to_tick -= 1;
+
+        let module = &self.module;
+        let (mut left, mut right) = (0.0, 0.0);
+        for channel in self.channels.iter_mut() {
+            if !channel.active {
+                continue;
+            }
+            let sample = match channel.sample
+                .and_then(|(i, s)| module.instruments.get(i).and_then(|i| i.samples.get(s)))
+            {
+                Some(sample) => sample,
+                None => continue
+            };
+            let data = &sample.data;
+            let index = channel.position as usize;
+            if index >= data.len() {
+                channel.active = false;
+                continue;
+            }
+            let next = data[(index + 1).min(data.len() - 1)];
+            let alpha = (channel.position - index as f64) as f32;
+            let value = data[index] + (next - data[index]) * alpha;
+            left += value * channel.left_gain;
+            right += value * channel.right_gain;
+
+            if channel.backwards {
+                channel.position -= channel.step;
+            } else {
+                channel.position += channel.step;
+            }
+            let (loop_start, loop_end) = (sample.loop_start as f64, sample.loop_end as f64);
+            match sample.loop_kind {
+                LoopKind::None => if channel.position >= data.len() as f64 {
+                    channel.active = false;
+                },
+                LoopKind::Forward => if channel.position >= loop_end {
+                    channel.position = loop_start + (channel.position - loop_end) % (loop_end - loop_start);
+                },
+                LoopKind::PingPong => {
+                    if !channel.backwards && channel.position >= loop_end {
+                        channel.position = (loop_end - (channel.position - loop_end)).max(loop_start) - 0.001;
+                        channel.backwards = true;
+                    } else if channel.backwards && channel.position < loop_start {
+                        channel.position = (loop_start + (loop_start - channel.position)).min(loop_end - 0.001);
+                        channel.backwards = false;
+                    }
+                }
+            }
+        }
+        frame[0] = left;
+        frame[1] = right;
+        self.frames += 1;
+        self.position.0.frame.store(self.frames, Ordering::Relaxed);
+        SourceState::Ready
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.frames)
+    }
+
+    /// Plays the song silently from its start order up to `frame`,
+    /// effects and tempo changes make the rows impossible to jump to directly.
+    /// A looping song wraps `frame` into its loop, a song that doesn't loop stops at its end.
+    fn seek(&mut self, frame: usize) -> bool {
+        let position = self.position.clone();
+        position.0.order.store(self.start_order, Ordering::Relaxed);
+        position.0.row.store(0, Ordering::Relaxed);
+        position.0.loops.store(0, Ordering::Relaxed);
+        position.0.frame.store(0, Ordering::Relaxed);
+        position.0.finished.store(false, Ordering::Relaxed);
+        *self = Self {
+            position,
+            ..ModulePlayer::new(self.module.clone())
+                .with_looping(self.looping)
+                .with_sample_rate(self.sample_rate)
+                .with_start_order(self.start_order)
+        };
+        let mut scratch = [0.0; 2];
+        let mut first_pass = None;
+        while self.frames < frame {
+            let played = self.frames;
+            if self.next_frame(&mut scratch) != SourceState::Ready {
+                return true;
+            }
+            if self.position.loops() > 0 {
+                first_pass = Some(played);
+                break;
+            }
+        }
+        // the frame counter restarts at the loop, so a target past it would never be reached
+        if let (Some(end), Some(restart)) = (first_pass, self.restart_frame) {
+            let target = if end > restart { restart + (frame - restart) % (end - restart) } else { restart };
+            while self.frames < target
+                && self.position.loops() < 2
+                && self.next_frame(&mut scratch) == SourceState::Ready {}
+        }
+        true
+    }
+
+    fn is_looping(&self) -> bool {
+        self.looping
+    }
+}
@@ -0,0 +1,171 @@
+use crate::audio::byte_reader::{ByteReader, invalid_data};
+use super::{Module, ModuleFormat, FrequencyMode, Pattern, Cell, Note, Effect, Instrument, Sample, LoopKind};
+
+const SAMPLE_COUNT: usize = 31;
+const SIGNATURE_OFFSET: usize = 1080;
+
+fn channels_from_signature(signature: &[u8]) -> Option<usize> {
+    match signature {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"FLT8" => Some(8),
+        [digit, b'C', b'H', b'N'] if digit.is_ascii_digit() => Some((digit - b'0') as usize),
+        [d0, d1, b'C', b'H'] | [d0, d1, b'C', b'N'] if d0.is_ascii_digit() && d1.is_ascii_digit() => {
+            Some(((d0 - b'0') * 10 + (d1 - b'0')) as usize)
+        }
+        _ => None
+    }
+}
+
+pub(crate) fn is_mod(bytes: &[u8]) -> bool {
+    bytes.len() >= SIGNATURE_OFFSET + 4 &&
+        channels_from_signature(&bytes[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 4]).is_some()
+}
+
+fn note_from_period(period: u16) -> Note {
+    if period == 0 {
+        return Note::None;
+    }
+    let semitones = 12.0 * (428.0 / period as f32).log2();
+    let note = 48 + semitones.round() as i32;
+    Note::On(note.clamp(0, 95) as u8)
+}
+
+/// Effects numbered 0-F, shared with XM
+pub(crate) fn convert_effect(command: u8, param: u8) -> Effect {
+    let (x, y) = (param >> 4, param & 0x0F);
+    match command {
+        0x0 if param != 0 => Effect::Arpeggio(param),
+        0x0 => Effect::None,
+        0x1 => Effect::PortaUp(param),
+        0x2 => Effect::PortaDown(param),
+        0x3 => Effect::TonePorta(param),
+        0x4 => Effect::Vibrato(param),
+        0x5 => Effect::TonePortaVolumeSlide(param),
+        0x6 => Effect::VibratoVolumeSlide(param),
+        0x8 => Effect::SetPanning(param),
+        0x9 => Effect::SampleOffset(param),
+        0xA => Effect::VolumeSlide(param),
+        0xB => Effect::PositionJump(param),
+        0xC => Effect::SetVolume(param.min(64)),
+        0xD => Effect::PatternBreak(x * 10 + y),
+        0xE => match x {
+            0x1 => Effect::FinePortaUp(y),
+            0x2 => Effect::FinePortaDown(y),
+            0x6 => Effect::PatternLoop(y),
+            0x8 => Effect::SetPanning(y * 17),
+            0x9 => Effect::Retrigger(y),
+            0xA => Effect::FineVolumeSlideUp(y),
+            0xB => Effect::FineVolumeSlideDown(y),
+            0xC => Effect::NoteCut(y),
+            0xD => Effect::NoteDelay(y),
+            0xE => Effect::PatternDelay(y),
+            _ => Effect::None
+        },
+        0xF if param == 0 => Effect::None,
+        0xF if param < 0x20 => Effect::SetSpeed(param),
+        0xF => Effect::SetTempo(param),
+        _ => Effect::None
+    }
+}
+
+pub(crate) fn parse(bytes: &[u8]) -> std::io::Result<Module> {
+    let mut reader = ByteReader::new(bytes);
+    let title = reader.text(20)?;
+
+    struct SampleHeader {
+        length: usize,
+        finetune: i8,
+        volume: u8,
+        loop_start: usize,
+        loop_length: usize
+    }
+    let mut headers = Vec::with_capacity(SAMPLE_COUNT);
+    for _ in 0..SAMPLE_COUNT {
+        reader.skip(22)?;
+        let length = reader.u16_be()? as usize * 2;
+        // finetune is a signed nibble in 1/8 semitones
+        let finetune = ((reader.u8()? & 0x0F) << 4) as i8 >> 4;
+        let volume = reader.u8()?.min(64);
+        let loop_start = reader.u16_be()? as usize * 2;
+        let loop_length = reader.u16_be()? as usize * 2;
+        headers.push(SampleHeader { length, finetune, volume, loop_start, loop_length });
+    }
+
+    let song_length = (reader.u8()? as usize).clamp(1, 128);
+    let restart_position = reader.u8()? as usize;
+    let order_table = reader.bytes(128)?;
+    let signature = reader.bytes(4)?;
+    let channels = channels_from_signature(signature)
+        .filter(|c| *c > 0)
+        .ok_or_else(|| invalid_data("unknown MOD signature"))?;
+
+    let orders: Vec<usize> = order_table[..song_length].iter().map(|o| *o as usize).collect();
+    let pattern_count = order_table.iter().map(|o| *o as usize + 1).max().unwrap_or(1);
+
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        let mut pattern = Pattern::empty(64, channels);
+        for cell in pattern.cells.iter_mut() {
+            let raw = reader.bytes(4)?;
+            let instrument = (raw[0] & 0xF0) | (raw[2] >> 4);
+            let period = ((raw[0] as u16 & 0x0F) << 8) | raw[1] as u16;
+            let command = raw[2] & 0x0F;
+            let param = raw[3];
+            let effect = match convert_effect(command, param) {
+                // protracker has no effect memory for these
+                Effect::PortaUp(0) | Effect::PortaDown(0) | Effect::VolumeSlide(0) => Effect::None,
+                effect => effect
+            };
+            *cell = Cell {
+                note: note_from_period(period),
+                instrument,
+                volume: None,
+                volume_effect: Effect::None,
+                effect
+            };
+        }
+        patterns.push(pattern);
+    }
+
+    let mut instruments = Vec::with_capacity(SAMPLE_COUNT);
+    for header in headers {
+        let raw = reader.bytes_truncated(header.length);
+        let data: Vec<f32> = raw.iter().map(|b| *b as i8 as f32 / 128.0).collect();
+        let loop_end = (header.loop_start + header.loop_length).min(data.len());
+        let loop_kind = if header.loop_length > 2 && header.loop_start < loop_end {
+            LoopKind::Forward
+        } else {
+            LoopKind::None
+        };
+        instruments.push(Instrument::from_sample(Sample {
+            data,
+            loop_kind,
+            loop_start: header.loop_start,
+            loop_end,
+            volume: header.volume,
+            c4_speed: 8363.0 * 2f32.powf(header.finetune as f32 / (12.0 * 8.0)),
+            panning: None
+        }));
+    }
+
+    // classic amiga LRRL layout, not fully hard-panned to be easier on headphones
+    let channel_panning = (0..channels)
+        .map(|i| if i % 4 == 0 || i % 4 == 3 { 0x40 } else { 0xC0 })
+        .collect();
+
+    let restart_position = if restart_position < orders.len() { restart_position } else { 0 };
+    Ok(Module {
+        title,
+        format: ModuleFormat::Mod,
+        channels,
+        orders,
+        restart_position,
+        patterns,
+        instruments,
+        channel_panning,
+        initial_speed: 6,
+        initial_tempo: 125,
+        initial_global_volume: 64,
+        frequency_mode: FrequencyMode::Amiga
+    })
+}
@@ -0,0 +1,246 @@
+use crate::audio::byte_reader::{ByteReader, invalid_data};
+use super::{Module, ModuleFormat, FrequencyMode, Pattern, Cell, Note, Effect, Instrument, Sample, LoopKind};
+
+const ORDER_END: u8 = 255;
+const ORDER_MARKER: u8 = 254;
+
+pub(crate) fn is_s3m(bytes: &[u8]) -> bool {
+    bytes.len() >= 0x60 && &bytes[0x2C..0x30] == b"SCRM"
+}
+
+fn convert_volume_slide(param: u8) -> Effect {
+    let (x, y) = (param >> 4, param & 0x0F);
+    match (x, y) {
+        (0x0F, 0x00) | (0x00, 0x0F) => Effect::VolumeSlide(param),
+        (x, 0x0F) => Effect::FineVolumeSlideUp(x),
+        (0x0F, y) => Effect::FineVolumeSlideDown(y),
+        _ => Effect::VolumeSlide(param)
+    }
+}
+
+fn convert_effect(command: u8, param: u8) -> Effect {
+    let (x, y) = (param >> 4, param & 0x0F);
+    match command {
+        // commands are letters, A is 1
+        1 => if param == 0 { Effect::None } else { Effect::SetSpeed(param) },
+        2 => Effect::PositionJump(param),
+        3 => Effect::PatternBreak(x * 10 + y),
+        4 => convert_volume_slide(param),
+        5 => match x {
+            0x0F => Effect::FinePortaDown(y),
+            0x0E => Effect::ExtraFinePortaDown(y),
+            _ => Effect::PortaDown(param)
+        },
+        6 => match x {
+            0x0F => Effect::FinePortaUp(y),
+            0x0E => Effect::ExtraFinePortaUp(y),
+            _ => Effect::PortaUp(param)
+        },
+        7 => Effect::TonePorta(param),
+        8 => Effect::Vibrato(param),
+        10 => Effect::Arpeggio(param),
+        11 => Effect::VibratoVolumeSlide(param),
+        12 => Effect::TonePortaVolumeSlide(param),
+        15 => Effect::SampleOffset(param),
+        17 => Effect::Retrigger(y),
+        19 => match x {
+            0x08 => Effect::SetPanning(y * 17),
+            0x0B => Effect::PatternLoop(y),
+            0x0C => Effect::NoteCut(y),
+            0x0D => Effect::NoteDelay(y),
+            0x0E => Effect::PatternDelay(y),
+            _ => Effect::None
+        },
+        20 if param >= 0x20 => Effect::SetTempo(param),
+        22 => Effect::SetGlobalVolume(param.min(64)),
+        24 => Effect::SetPanning((param as u16 * 2).min(255) as u8),
+        _ => Effect::None
+    }
+}
+
+fn parse_pattern(reader: &mut ByteReader, channel_map: &[Option<usize>], channels: usize) -> std::io::Result<Pattern> {
+    let mut pattern = Pattern::empty(64, channels);
+    let _packed_length = reader.u16_le()?;
+    let mut row = 0;
+    while row < 64 {
+        let what = reader.u8()?;
+        if what == 0 {
+            row += 1;
+            continue;
+        }
+        let mut cell = Cell::EMPTY;
+        if what & 0x20 != 0 {
+            cell.note = match reader.u8()? {
+                255 => Note::None,
+                254 => Note::Cut,
+                note => Note::On(((note >> 4) * 12 + (note & 0x0F)).min(95))
+            };
+            cell.instrument = reader.u8()?;
+        }
+        if what & 0x40 != 0 {
+            cell.volume = Some(reader.u8()?.min(64));
+        }
+        if what & 0x80 != 0 {
+            let command = reader.u8()?;
+            let param = reader.u8()?;
+            cell.effect = convert_effect(command, param);
+        }
+        if let Some(Some(channel)) = channel_map.get((what & 0x1F) as usize) {
+            pattern.cells[row * channels + channel] = cell;
+        }
+    }
+    Ok(pattern)
+}
+
+fn parse_instrument(bytes: &[u8], offset: usize, signed_samples: bool) -> std::io::Result<Instrument> {
+    let mut reader = ByteReader::new(bytes);
+    reader.seek(offset)?;
+    let kind = reader.u8()?;
+    reader.skip(12)?;
+    let memseg_high = reader.u8()? as usize;
+    let memseg_low = reader.u16_le()? as usize;
+    let length = reader.u32_le()? as usize;
+    let loop_start = reader.u32_le()? as usize;
+    let loop_end = reader.u32_le()? as usize;
+    let volume = reader.u8()?.min(64);
+    reader.skip(2)?;
+    let flags = reader.u8()?;
+    let c2_speed = reader.u32_le()?;
+
+    let mut data = Vec::new();
+    if offset != 0 && kind == 1 && length > 0 {
+        let is_16bit = flags & 4 != 0;
+        let bytes_per_sample = if is_16bit { 2 } else { 1 };
+        reader.seek(((memseg_high << 16) | memseg_low) * 16)?;
+        let raw = reader.bytes_truncated(length * bytes_per_sample);
+        data = if is_16bit {
+            raw.chunks_exact(2)
+                .map(|b| {
+                    let value = u16::from_le_bytes([b[0], b[1]]);
+                    let value = if signed_samples { value as i16 } else { (value ^ 0x8000) as i16 };
+                    value as f32 / 32768.0
+                })
+                .collect()
+        } else {
+            raw.iter()
+                .map(|b| {
+                    let value = if signed_samples { *b as i8 } else { (*b ^ 0x80) as i8 };
+                    value as f32 / 128.0
+                })
+                .collect()
+        };
+    }
+    let loop_end = loop_end.min(data.len());
+    let loop_kind = if flags & 1 != 0 && loop_start < loop_end {
+        LoopKind::Forward
+    } else {
+        LoopKind::None
+    };
+    Ok(Instrument::from_sample(Sample {
+        data,
+        loop_kind,
+        loop_start,
+        loop_end,
+        volume,
+        c4_speed: if c2_speed == 0 { 8363.0 } else { c2_speed as f32 },
+        panning: None
+    }))
+}
+
+pub(crate) fn parse(bytes: &[u8]) -> std::io::Result<Module> {
+    let mut reader = ByteReader::new(bytes);
+    let title = reader.text(28)?;
+    reader.seek(0x20)?;
+    let order_count = reader.u16_le()? as usize;
+    let instrument_count = reader.u16_le()? as usize;
+    let pattern_count = reader.u16_le()? as usize;
+    let _flags = reader.u16_le()?;
+    let _tracker_version = reader.u16_le()?;
+    let sample_format = reader.u16_le()?;
+    reader.seek(0x30)?;
+    let global_volume = reader.u8()?.min(64);
+    let initial_speed = reader.u8()?;
+    let initial_tempo = reader.u8()?;
+    let master_volume = reader.u8()?;
+    let _ultra_click = reader.u8()?;
+    let default_panning = reader.u8()?;
+    reader.seek(0x40)?;
+    let channel_settings = reader.bytes(32)?;
+
+    let raw_orders = reader.bytes(order_count)?;
+    let mut instrument_pointers = Vec::with_capacity(instrument_count);
+    for _ in 0..instrument_count {
+        instrument_pointers.push(reader.u16_le()? as usize * 16);
+    }
+    let mut pattern_pointers = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        pattern_pointers.push(reader.u16_le()? as usize * 16);
+    }
+    let panning_table = if default_panning == 0xFC {
+        Some(reader.bytes(32)?)
+    } else {
+        None
+    };
+
+    let mut channel_map = vec![None; 32];
+    let mut channel_panning = Vec::new();
+    for (i, setting) in channel_settings.iter().enumerate() {
+        if *setting >= 16 {
+            continue;
+        }
+        channel_map[i] = Some(channel_panning.len());
+        let mut panning = if master_volume & 0x80 == 0 {
+            0x80
+        } else if *setting < 8 {
+            0x30
+        } else {
+            0xC0
+        };
+        if let Some(table) = panning_table {
+            if table[i] & 0x20 != 0 {
+                panning = (table[i] & 0x0F) * 17;
+            }
+        }
+        channel_panning.push(panning);
+    }
+    let channels = channel_panning.len();
+    if channels == 0 {
+        return Err(invalid_data("S3M module has no enabled channels"));
+    }
+
+    let orders: Vec<usize> = raw_orders.iter()
+        .take_while(|o| **o != ORDER_END)
+        .filter(|o| **o != ORDER_MARKER)
+        .map(|o| *o as usize)
+        .collect();
+
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for pointer in pattern_pointers {
+        if pointer == 0 {
+            patterns.push(Pattern::empty(64, channels));
+            continue;
+        }
+        reader.seek(pointer)?;
+        patterns.push(parse_pattern(&mut reader, &channel_map, channels)?);
+    }
+
+    let signed_samples = sample_format == 1;
+    let instruments = instrument_pointers.iter()
+        .map(|pointer| parse_instrument(bytes, *pointer, signed_samples))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    Ok(Module {
+        title,
+        format: ModuleFormat::S3m,
+        channels,
+        orders,
+        restart_position: 0,
+        patterns,
+        instruments,
+        channel_panning,
+        initial_speed: if initial_speed == 0 { 6 } else { initial_speed },
+        initial_tempo: if initial_tempo < 32 { 125 } else { initial_tempo },
+        initial_global_volume: global_volume,
+        frequency_mode: FrequencyMode::Amiga
+    })
+}
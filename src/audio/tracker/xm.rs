@@ -0,0 +1,293 @@
+use crate::audio::byte_reader::{ByteReader, invalid_data};
+use super::{Module, ModuleFormat, FrequencyMode, Pattern, Cell, Note, Effect, Instrument, Sample, LoopKind, Envelope};
+use super::protracker;
+
+const SIGNATURE: &[u8] = b"Extended Module: ";
+const KEY_OFF: u8 = 97;
+
+pub(crate) fn is_xm(bytes: &[u8]) -> bool {
+    bytes.starts_with(SIGNATURE)
+}
+
+fn convert_effect(command: u8, param: u8) -> Effect {
+    let (x, y) = (param >> 4, param & 0x0F);
+    match command {
+        0x00..=0x0F => protracker::convert_effect(command, param),
+        // G
+        0x10 => Effect::SetGlobalVolume(param.min(64)),
+        // H
+        0x11 => Effect::GlobalVolumeSlide(param),
+        // K
+        0x14 => Effect::KeyOff(param),
+        // P
+        0x19 => Effect::PanningSlide(param),
+        // R
+        0x1B => Effect::Retrigger(y),
+        // X
+        0x21 => match x {
+            0x1 => Effect::ExtraFinePortaUp(y),
+            0x2 => Effect::ExtraFinePortaDown(y),
+            _ => Effect::None
+        },
+        _ => Effect::None
+    }
+}
+
+fn convert_volume_column(value: u8, cell: &mut Cell) {
+    let y = value & 0x0F;
+    match value {
+        0x10..=0x50 => cell.volume = Some(value - 0x10),
+        0x60..=0x6F => cell.volume_effect = Effect::VolumeSlide(y),
+        0x70..=0x7F => cell.volume_effect = Effect::VolumeSlide(y << 4),
+        0x80..=0x8F => cell.volume_effect = Effect::FineVolumeSlideDown(y),
+        0x90..=0x9F => cell.volume_effect = Effect::FineVolumeSlideUp(y),
+        0xB0..=0xBF => cell.volume_effect = Effect::Vibrato(y),
+        0xC0..=0xCF => cell.volume_effect = Effect::SetPanning(y * 17),
+        0xD0..=0xDF => cell.volume_effect = Effect::PanningSlide(y),
+        0xE0..=0xEF => cell.volume_effect = Effect::PanningSlide(y << 4),
+        0xF0..=0xFF => cell.volume_effect = Effect::TonePorta(y << 4),
+        _ => {}
+    }
+}
+
+fn parse_pattern(reader: &mut ByteReader, channels: usize) -> std::io::Result<Pattern> {
+    let start = reader.position();
+    let header_length = reader.u32_le()? as usize;
+    let _packing = reader.u8()?;
+    let rows = (reader.u16_le()? as usize).max(1);
+    let packed_size = reader.u16_le()? as usize;
+    reader.seek(start + header_length)?;
+
+    let mut pattern = Pattern::empty(rows, channels);
+    if packed_size == 0 {
+        return Ok(pattern);
+    }
+    let data = reader.bytes(packed_size)?;
+    let mut data = ByteReader::new(data);
+    for cell in pattern.cells.iter_mut() {
+        let first = data.u8()?;
+        let (flags, first_note) = if first & 0x80 != 0 {
+            (first, None)
+        } else {
+            (0x1F, Some(first))
+        };
+        let note = match first_note {
+            Some(note) => note,
+            None if flags & 0x01 != 0 => data.u8()?,
+            None => 0
+        };
+        cell.note = match note {
+            0 => Note::None,
+            KEY_OFF => Note::Off,
+            note => Note::On((note - 1).min(95))
+        };
+        if flags & 0x02 != 0 {
+            cell.instrument = data.u8()?;
+        }
+        if flags & 0x04 != 0 {
+            convert_volume_column(data.u8()?, cell);
+        }
+        let command = if flags & 0x08 != 0 { data.u8()? } else { 0 };
+        let param = if flags & 0x10 != 0 { data.u8()? } else { 0 };
+        cell.effect = convert_effect(command, param);
+    }
+    Ok(pattern)
+}
+
+fn read_envelope(
+    raw_points: &[u8],
+    point_count: u8,
+    sustain: u8,
+    loop_start: u8,
+    loop_end: u8,
+    flags: u8
+) -> std::io::Result<Option<Envelope>> {
+    if flags & 1 == 0 || point_count == 0 {
+        return Ok(None);
+    }
+    let mut reader = ByteReader::new(raw_points);
+    let mut points = Vec::with_capacity(point_count as usize);
+    for _ in 0..point_count.min(12) {
+        let tick = reader.u16_le()?;
+        let value = reader.u16_le()?.min(64) as u8;
+        points.push((tick, value));
+    }
+    let count = points.len();
+    Ok(Some(Envelope {
+        points,
+        sustain: if flags & 2 != 0 && (sustain as usize) < count { Some(sustain as usize) } else { None },
+        repeat: if flags & 4 != 0 && loop_start <= loop_end && (loop_end as usize) < count {
+            Some((loop_start as usize, loop_end as usize))
+        } else {
+            None
+        }
+    }))
+}
+
+fn parse_instrument(reader: &mut ByteReader) -> std::io::Result<Instrument> {
+    let start = reader.position();
+    let header_size = reader.u32_le()? as usize;
+    reader.skip(22)?;
+    let _kind = reader.u8()?;
+    let sample_count = reader.u16_le()? as usize;
+
+    let mut instrument = Instrument {
+        samples: Vec::new(),
+        keymap: [0; 96],
+        volume_envelope: None,
+        panning_envelope: None,
+        fadeout: 0
+    };
+    if sample_count == 0 {
+        reader.seek(start + header_size)?;
+        return Ok(instrument);
+    }
+
+    let sample_header_size = reader.u32_le()? as usize;
+    instrument.keymap.copy_from_slice(reader.bytes(96)?);
+    let volume_points = reader.bytes(48)?;
+    let panning_points = reader.bytes(48)?;
+    let volume_point_count = reader.u8()?;
+    let panning_point_count = reader.u8()?;
+    let volume_sustain = reader.u8()?;
+    let volume_loop_start = reader.u8()?;
+    let volume_loop_end = reader.u8()?;
+    let panning_sustain = reader.u8()?;
+    let panning_loop_start = reader.u8()?;
+    let panning_loop_end = reader.u8()?;
+    let volume_flags = reader.u8()?;
+    let panning_flags = reader.u8()?;
+    reader.skip(4)?;
+    instrument.fadeout = reader.u16_le()?;
+    instrument.volume_envelope = read_envelope(
+        volume_points, volume_point_count, volume_sustain, volume_loop_start, volume_loop_end, volume_flags
+    )?;
+    instrument.panning_envelope = read_envelope(
+        panning_points, panning_point_count, panning_sustain, panning_loop_start, panning_loop_end, panning_flags
+    )?;
+    reader.seek(start + header_size)?;
+
+    struct SampleHeader {
+        length: usize,
+        loop_start: usize,
+        loop_length: usize,
+        volume: u8,
+        finetune: i8,
+        flags: u8,
+        panning: u8,
+        relative_note: i8
+    }
+    let mut headers = Vec::with_capacity(sample_count);
+    for _ in 0..sample_count {
+        let header_start = reader.position();
+        headers.push(SampleHeader {
+            length: reader.u32_le()? as usize,
+            loop_start: reader.u32_le()? as usize,
+            loop_length: reader.u32_le()? as usize,
+            volume: reader.u8()?.min(64),
+            finetune: reader.i8()?,
+            flags: reader.u8()?,
+            panning: reader.u8()?,
+            relative_note: reader.i8()?
+        });
+        reader.seek(header_start + sample_header_size)?;
+    }
+
+    for header in headers {
+        let is_16bit = header.flags & 0x10 != 0;
+        let raw = reader.bytes_truncated(header.length);
+        // sample data is delta encoded
+        let data: Vec<f32> = if is_16bit {
+            let mut acc = 0i16;
+            raw.chunks_exact(2)
+                .map(|b| {
+                    acc = acc.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+                    acc as f32 / 32768.0
+                })
+                .collect()
+        } else {
+            let mut acc = 0i8;
+            raw.iter()
+                .map(|b| {
+                    acc = acc.wrapping_add(*b as i8);
+                    acc as f32 / 128.0
+                })
+                .collect()
+        };
+        let (loop_start, loop_length) = if is_16bit {
+            (header.loop_start / 2, header.loop_length / 2)
+        } else {
+            (header.loop_start, header.loop_length)
+        };
+        let loop_end = (loop_start + loop_length).min(data.len());
+        let loop_kind = match header.flags & 0x03 {
+            _ if loop_start >= loop_end => LoopKind::None,
+            1 => LoopKind::Forward,
+            2 => LoopKind::PingPong,
+            _ => LoopKind::None
+        };
+        instrument.samples.push(Sample {
+            data,
+            loop_kind,
+            loop_start,
+            loop_end,
+            volume: header.volume,
+            c4_speed: Sample::c4_speed_from_tuning(header.relative_note, header.finetune),
+            panning: Some(header.panning)
+        });
+    }
+    Ok(instrument)
+}
+
+pub(crate) fn parse(bytes: &[u8]) -> std::io::Result<Module> {
+    let mut reader = ByteReader::new(bytes);
+    reader.skip(SIGNATURE.len())?;
+    let title = reader.text(20)?;
+    reader.seek(58)?;
+    let version = reader.u16_le()?;
+    if version < 0x0104 {
+        return Err(invalid_data("XM modules older than version 1.04 are not supported"));
+    }
+    let header_size = reader.u32_le()? as usize;
+    let song_length = reader.u16_le()? as usize;
+    let restart_position = reader.u16_le()? as usize;
+    let channels = reader.u16_le()? as usize;
+    let pattern_count = reader.u16_le()? as usize;
+    let instrument_count = reader.u16_le()? as usize;
+    let flags = reader.u16_le()?;
+    let initial_speed = reader.u16_le()?;
+    let initial_tempo = reader.u16_le()?;
+    let order_table = reader.bytes(256)?;
+    if channels == 0 || channels > 64 {
+        return Err(invalid_data("XM module has an invalid channel count"));
+    }
+
+    let orders: Vec<usize> = order_table[..song_length.min(256)].iter().map(|o| *o as usize).collect();
+
+    reader.seek(60 + header_size)?;
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        patterns.push(parse_pattern(&mut reader, channels)?);
+    }
+    let mut instruments = Vec::with_capacity(instrument_count);
+    for _ in 0..instrument_count {
+        instruments.push(parse_instrument(&mut reader)?);
+    }
+
+    // the song length can claim more orders than the table holds
+    let restart_position = if restart_position < orders.len() { restart_position } else { 0 };
+    Ok(Module {
+        title,
+        format: ModuleFormat::Xm,
+        channels,
+        orders,
+        restart_position,
+        patterns,
+        instruments,
+        channel_panning: vec![0x80; channels],
+        initial_speed: if initial_speed == 0 { 6 } else { initial_speed.min(31) as u8 },
+        initial_tempo: initial_tempo.clamp(32, 255) as u8,
+        initial_global_volume: 64,
+        frequency_mode: if flags & 1 != 0 { FrequencyMode::Linear } else { FrequencyMode::Amiga }
+    })
+}
@@ -0,0 +1,101 @@
+//! Volume handling: slider values mapped through a [`VolumeCurve`] or linear [`Gain`].
+//!
+//! Everything is validated on the game thread, the audio thread only ever sees linear gains.
+use std::fmt::{Display, Formatter};
+use crate::audio::mixer::Volume;
+
+/// highest gain accepted anywhere in the mixer, in dB
+pub const MAX_GAIN_DB: f32 = 24.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// error produced when a volume or gain is out of range
+pub enum VolumeError {
+    /// the value is NaN or infinite
+    NotFinite,
+    /// a [`Volume`] outside of `0.0..=1.0`
+    VolumeOutOfRange(f32),
+    /// a [`Gain`] below zero or above [`MAX_GAIN_DB`]
+    GainOutOfRange(f32)
+}
+impl Display for VolumeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VolumeError::NotFinite => write!(f, "volume is not a finite number"),
+            VolumeError::VolumeOutOfRange(value) => write!(f, "volume {} is outside of 0.0..=1.0", value),
+            VolumeError::GainOutOfRange(value) => write!(f, "gain {} is outside of 0.0..=+{}dB", value, MAX_GAIN_DB)
+        }
+    }
+}
+impl std::error::Error for VolumeError {}
+
+/// Linear amplitude factor, 1.0 leaves a sound as it is.
+/// Values above 1.0 make quiet assets louder, up to [`MAX_GAIN_DB`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gain(pub f32);
+impl Gain {
+    pub const UNITY: Gain = Gain(1.0);
+    pub const SILENCE: Gain = Gain(0.0);
+
+    pub fn new(gain: f32) -> Result<Self, VolumeError> {
+        Gain(gain).validate()
+    }
+    /// `f32::NEG_INFINITY` is silence
+    pub fn from_db(db: f32) -> Result<Self, VolumeError> {
+        if db.is_nan() {
+            return Err(VolumeError::NotFinite);
+        }
+        Gain(10f32.powf(db / 20.0)).validate()
+    }
+    pub fn db(self) -> f32 {
+        20.0 * self.0.log10()
+    }
+    pub fn validate(self) -> Result<Self, VolumeError> {
+        if !self.0.is_finite() {
+            return Err(VolumeError::NotFinite);
+        }
+        if self.0 < 0.0 || self.0 > 10f32.powf(MAX_GAIN_DB / 20.0) {
+            return Err(VolumeError::GainOutOfRange(self.0));
+        }
+        Ok(self)
+    }
+}
+
+/// How a [`Volume`] slider position in `0.0..=1.0` turns into a [`Gain`]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum VolumeCurve {
+    Linear,
+    /// the mixer's default, changes are easy to hear in the upper half of the slider
+    #[default]
+    Squared,
+    Cubic,
+    /// constant dB steps over the given range, zero is still silence
+    DbTaper(f32)
+}
+impl VolumeCurve {
+    pub fn gain(self, volume: Volume) -> Result<Gain, VolumeError> {
+        let value = volume.validate()?.0;
+        let gain = match self {
+            VolumeCurve::Linear => value,
+            VolumeCurve::Squared => value * value,
+            VolumeCurve::Cubic => value * value * value,
+            VolumeCurve::DbTaper(_) if value == 0.0 => 0.0,
+            VolumeCurve::DbTaper(range_db) => 10f32.powf(-range_db.abs() * (1.0 - value) / 20.0)
+        };
+        Ok(Gain(gain))
+    }
+}
+
+/// Volume of a [`crate::audio::PlaybackBuilder`] until the mixer resolves it with its curve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Level {
+    Volume(Volume),
+    Gain(Gain)
+}
+impl Level {
+    pub(crate) fn gain(self, curve: VolumeCurve) -> Result<Gain, VolumeError> {
+        match self {
+            Level::Volume(volume) => curve.gain(volume),
+            Level::Gain(gain) => gain.validate()
+        }
+    }
+}
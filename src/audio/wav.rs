@@ -0,0 +1,360 @@
+//! RIFF WAV decoding into [`Sound`] and encoding back.
+//!
+//! Supports integer PCM (8-bit unsigned, 16, 24 and 32-bit signed), IEEE float,
+//! IMA ADPCM and Microsoft ADPCM, including `WAVE_FORMAT_EXTENSIBLE` headers.
+use std::io::Write;
+use crate::audio::byte_reader::{ByteReader, invalid_data};
+use crate::audio::mixer::{Sound, PlaybackStyle};
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_MS_ADPCM: u16 = 0x0002;
+const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const FORMAT_IMA_ADPCM: u16 = 0x0011;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+struct Format {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    block_align: usize,
+    bits_per_sample: u16,
+    extra: Vec<u8>
+}
+
+fn format_name(tag: u16) -> &'static str {
+    match tag {
+        0x0006 => "A-law",
+        0x0007 => "mu-law",
+        0x0031 => "GSM 6.10",
+        0x0050 => "MPEG",
+        0x0055 => "MPEG Layer 3",
+        0x0161..=0x0163 => "Windows Media Audio",
+        _ => "unknown"
+    }
+}
+
+fn read_format(chunk: &[u8]) -> std::io::Result<Format> {
+    let mut reader = ByteReader::new(chunk);
+    let mut tag = reader.u16_le()?;
+    let channels = reader.u16_le()?;
+    let sample_rate = reader.u32_le()?;
+    let _byte_rate = reader.u32_le()?;
+    let block_align = reader.u16_le()? as usize;
+    let bits_per_sample = reader.u16_le()?;
+    let extra = if chunk.len() >= 18 {
+        let extra_size = reader.u16_le()? as usize;
+        reader.bytes_truncated(extra_size).to_vec()
+    } else {
+        Vec::new()
+    };
+    if tag == FORMAT_EXTENSIBLE {
+        // valid bits, channel mask, then the sub format GUID which starts with the real tag
+        if extra.len() < 10 {
+            return Err(invalid_data("WAV extensible format header is truncated"));
+        }
+        tag = u16::from_le_bytes([extra[6], extra[7]]);
+    }
+    if channels == 0 || channels > 2 {
+        return Err(invalid_data(&format!("unsupported WAV channel count: {}", channels)));
+    }
+    if sample_rate == 0 {
+        return Err(invalid_data("WAV file declares a zero sample rate"));
+    }
+    Ok(Format { tag, channels, sample_rate, block_align, bits_per_sample, extra })
+}
+
+fn decode_pcm(data: &[u8], bits_per_sample: u16) -> std::io::Result<Vec<f32>> {
+    let samples = match bits_per_sample {
+        8 => data.iter().map(|b| (*b as f32 - 128.0) / 128.0).collect(),
+        16 => data.chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        24 => data.chunks_exact(3)
+            .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0)
+            .collect(),
+        32 => data.chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0)
+            .collect(),
+        bits => return Err(invalid_data(&format!("unsupported WAV PCM bit depth: {}", bits)))
+    };
+    Ok(samples)
+}
+
+fn decode_float(data: &[u8], bits_per_sample: u16) -> std::io::Result<Vec<f32>> {
+    let samples = match bits_per_sample {
+        32 => data.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        64 => data.chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        bits => return Err(invalid_data(&format!("unsupported WAV float bit depth: {}", bits)))
+    };
+    Ok(samples)
+}
+
+const IMA_INDEX_TABLE: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+const IMA_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45,
+    50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230,
+    253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963,
+    1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327,
+    3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487,
+    12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767
+];
+
+struct ImaChannel {
+    predictor: i32,
+    step_index: i32
+}
+impl ImaChannel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = IMA_STEP_TABLE[self.step_index as usize];
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 { diff += step >> 2; }
+        if nibble & 2 != 0 { diff += step >> 1; }
+        if nibble & 4 != 0 { diff += step; }
+        if nibble & 8 != 0 {
+            self.predictor -= diff;
+        } else {
+            self.predictor += diff;
+        }
+        self.predictor = self.predictor.clamp(i16::MIN as i32, i16::MAX as i32);
+        self.step_index = (self.step_index + IMA_INDEX_TABLE[nibble as usize]).clamp(0, 88);
+        self.predictor as i16
+    }
+}
+
+fn decode_ima_adpcm(data: &[u8], format: &Format) -> std::io::Result<Vec<f32>> {
+    let channels = format.channels as usize;
+    if format.bits_per_sample != 4 {
+        return Err(invalid_data("only 4-bit IMA ADPCM WAV files are supported"));
+    }
+    if format.block_align <= 4 * channels {
+        return Err(invalid_data("IMA ADPCM WAV file has an invalid block size"));
+    }
+    let mut samples = Vec::new();
+    for block in data.chunks(format.block_align) {
+        if block.len() < 4 * channels {
+            break;
+        }
+        let mut states = Vec::with_capacity(channels);
+        for header in block.chunks_exact(4).take(channels) {
+            let predictor = i16::from_le_bytes([header[0], header[1]]);
+            samples.push(predictor as f32 / 32768.0);
+            states.push(ImaChannel {
+                predictor: predictor as i32,
+                step_index: (header[2] as i32).min(88)
+            });
+        }
+        // each channel gets 4 bytes (8 samples) at a time, low nibble first
+        let mut decoded = vec![0i16; 8 * channels];
+        for group in block[4 * channels..].chunks_exact(4 * channels) {
+            for (channel, state) in states.iter_mut().enumerate() {
+                let bytes = &group[channel * 4..channel * 4 + 4];
+                for (i, byte) in bytes.iter().enumerate() {
+                    decoded[(i * 2) * channels + channel] = state.decode(byte & 0x0F);
+                    decoded[(i * 2 + 1) * channels + channel] = state.decode(byte >> 4);
+                }
+            }
+            samples.extend(decoded.iter().map(|s| *s as f32 / 32768.0));
+        }
+    }
+    Ok(samples)
+}
+
+const MS_ADAPTATION_TABLE: [i32; 16] = [
+    230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230
+];
+const MS_DEFAULT_COEFFICIENTS: [(i32, i32); 7] = [
+    (256, 0), (512, -256), (0, 0), (192, 64), (240, 0), (460, -208), (392, -232)
+];
+
+struct MsChannel {
+    coefficients: (i32, i32),
+    delta: i32,
+    sample1: i32,
+    sample2: i32
+}
+/// corrupt data can grow the step size without bound, the reference decoder caps it here
+const MS_MAX_DELTA: i32 = i32::MAX / 768;
+
+impl MsChannel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let signed = ((nibble << 4) as i8 >> 4) as i64;
+        // coefficients come from the file, two full scale products can overflow an i32
+        let prediction = (self.sample1 as i64 * self.coefficients.0 as i64
+            + self.sample2 as i64 * self.coefficients.1 as i64) >> 8;
+        let sample = (prediction + signed * self.delta as i64).clamp(i16::MIN as i64, i16::MAX as i64) as i32;
+        self.sample2 = self.sample1;
+        self.sample1 = sample;
+        self.delta = ((MS_ADAPTATION_TABLE[nibble as usize] * self.delta) >> 8).clamp(16, MS_MAX_DELTA);
+        sample as i16
+    }
+}
+
+fn decode_ms_adpcm(data: &[u8], format: &Format) -> std::io::Result<Vec<f32>> {
+    let channels = format.channels as usize;
+    if format.bits_per_sample != 4 {
+        return Err(invalid_data("only 4-bit Microsoft ADPCM WAV files are supported"));
+    }
+    if format.block_align <= 7 * channels {
+        return Err(invalid_data("Microsoft ADPCM WAV file has an invalid block size"));
+    }
+    let coefficients = if format.extra.len() >= 4 {
+        let mut reader = ByteReader::new(&format.extra);
+        let _samples_per_block = reader.u16_le()?;
+        let count = reader.u16_le()? as usize;
+        let mut coefficients = Vec::with_capacity(count);
+        for _ in 0..count {
+            let c1 = reader.u16_le()? as i16 as i32;
+            let c2 = reader.u16_le()? as i16 as i32;
+            coefficients.push((c1, c2));
+        }
+        coefficients
+    } else {
+        MS_DEFAULT_COEFFICIENTS.to_vec()
+    };
+
+    let mut samples = Vec::new();
+    for block in data.chunks(format.block_align) {
+        if block.len() < 7 * channels {
+            break;
+        }
+        let mut reader = ByteReader::new(block);
+        let mut states = Vec::with_capacity(channels);
+        for _ in 0..channels {
+            let predictor = reader.u8()? as usize;
+            let coefficients = *coefficients.get(predictor)
+                .ok_or_else(|| invalid_data("Microsoft ADPCM block uses an unknown predictor"))?;
+            states.push(MsChannel { coefficients, delta: 0, sample1: 0, sample2: 0 });
+        }
+        for state in states.iter_mut() {
+            state.delta = (reader.u16_le()? as i16 as i32).max(16);
+        }
+        for state in states.iter_mut() {
+            state.sample1 = reader.u16_le()? as i16 as i32;
+        }
+        for state in states.iter_mut() {
+            state.sample2 = reader.u16_le()? as i16 as i32;
+        }
+        // the header samples come out oldest first
+        samples.extend(states.iter().map(|s| s.sample2 as f32 / 32768.0));
+        samples.extend(states.iter().map(|s| s.sample1 as f32 / 32768.0));
+
+        // nibbles are interleaved by channel, high nibble first
+        let mut channel = 0;
+        for byte in &block[7 * channels..] {
+            for nibble in [byte >> 4, byte & 0x0F].iter() {
+                samples.push(states[channel].decode(*nibble) as f32 / 32768.0);
+                channel = (channel + 1) % channels;
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// Decodes a RIFF WAV file
+pub(crate) fn decode(bytes: &[u8]) -> std::io::Result<Sound> {
+    let mut reader = ByteReader::new(bytes);
+    if reader.bytes(4)? != b"RIFF" {
+        return Err(invalid_data("not a RIFF file"));
+    }
+    let _riff_size = reader.u32_le()?;
+    if reader.bytes(4)? != b"WAVE" {
+        return Err(invalid_data("RIFF file is not a WAVE file"));
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut frame_count = None;
+    while reader.position() + 8 <= bytes.len() {
+        let id = reader.bytes(4)?;
+        let size = reader.u32_le()? as usize;
+        // the data chunk size of a file that is still being written may be bogus
+        let chunk = reader.bytes_truncated(size);
+        if size % 2 == 1 {
+            let _ = reader.bytes_truncated(1);
+        }
+        match id {
+            b"fmt " => format = Some(read_format(chunk)?),
+            b"data" => data = Some(chunk),
+            b"fact" if chunk.len() >= 4 => {
+                frame_count = Some(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize)
+            }
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid_data("WAV file has no fmt chunk"))?;
+    let data = data.ok_or_else(|| invalid_data("WAV file has no data chunk"))?;
+
+    let mut samples = match format.tag {
+        FORMAT_PCM => decode_pcm(data, format.bits_per_sample)?,
+        FORMAT_IEEE_FLOAT => decode_float(data, format.bits_per_sample)?,
+        FORMAT_IMA_ADPCM => decode_ima_adpcm(data, &format)?,
+        FORMAT_MS_ADPCM => decode_ms_adpcm(data, &format)?,
+        tag => return Err(invalid_data(&format!(
+            "unsupported WAV format 0x{:04X} ({})", tag, format_name(tag)
+        )))
+    };
+    let channels = format.channels as usize;
+    // compressed blocks are padded, the fact chunk tells the real length
+    if let (Some(frames), FORMAT_IMA_ADPCM) | (Some(frames), FORMAT_MS_ADPCM) = (frame_count, format.tag) {
+        samples.truncate(frames * channels);
+    }
+    samples.truncate(samples.len() - samples.len() % channels);
+
+    Ok(Sound {
+        sample_rate: format.sample_rate as f32,
+        channels: format.channels,
+        samples,
+        playback_style: PlaybackStyle::Once
+    })
+}
+
+const HEADER_SIZE: u32 = 58;
+
+/// Writes the header of a 32-bit float WAV holding `sample_count` interleaved samples
+pub(crate) fn write_header<W: Write>(
+    writer: &mut W,
+    sample_rate: u32,
+    channels: u16,
+    sample_count: usize
+) -> std::io::Result<()> {
+    let data_size = (sample_count * 4) as u32;
+    let block_align = channels * 4;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&18u32.to_le_bytes())?;
+    writer.write_all(&FORMAT_IEEE_FLOAT.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+
+    // non-PCM files are expected to carry the frame count
+    writer.write_all(b"fact")?;
+    writer.write_all(&4u32.to_le_bytes())?;
+    writer.write_all(&((sample_count / channels.max(1) as usize) as u32).to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
+
+pub(crate) fn write_samples<W: Write>(writer: &mut W, samples: &[f32]) -> std::io::Result<()> {
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Encodes a whole sound as a 32-bit float WAV, so decoding it again is lossless
+pub(crate) fn encode<W: Write>(sound: &Sound, writer: &mut W) -> std::io::Result<()> {
+    write_header(writer, sound.sample_rate as u32, sound.channels, sound.samples.len())?;
+    write_samples(writer, &sound.samples)
+}
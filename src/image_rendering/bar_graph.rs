@@ -0,0 +1,98 @@
+use crate::image_rendering::blittable::{Blittable, Rect};
+
+/// Vertical bars of values in `0.0..=1.0`, e.g. level meters or a spectrum.
+/// Blit it like any other [`Blittable`] with a [`crate::image_rendering::blittable::BlitBuilder`].
+pub struct BarGraph {
+    values: Vec<f32>,
+    width: usize,
+    height: usize,
+    bar_color: u32,
+    background: Option<u32>,
+    gap: usize
+}
+impl BarGraph {
+    pub fn new(values: Vec<f32>, width: usize, height: usize) -> Self {
+        Self {
+            values,
+            width,
+            height,
+            bar_color: 0xFF_FF_FF,
+            background: None,
+            gap: 1
+        }
+    }
+    pub fn with_bar_color(self, bar_color: u32) -> Self {
+        Self {
+            bar_color,
+            ..self
+        }
+    }
+    /// fills the space above the bars, otherwise it is left untouched
+    pub fn with_background(self, background: u32) -> Self {
+        Self {
+            background: Some(background),
+            ..self
+        }
+    }
+    /// empty columns between the bars
+    pub fn with_gap(self, gap: usize) -> Self {
+        Self {
+            gap,
+            ..self
+        }
+    }
+    pub fn set_values(&mut self, values: &[f32]) {
+        self.values.clear();
+        self.values.extend_from_slice(values);
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Option<u32> {
+        let count = self.values.len();
+        if count == 0 {
+            return self.background;
+        }
+        let bar = x * count / self.width.max(1);
+        let bar_start = bar * self.width / count;
+        let bar_end = (bar + 1) * self.width / count;
+        let in_gap = bar_end - bar_start > self.gap && x + self.gap >= bar_end;
+        let value = self.values[bar].clamp(0.0, 1.0);
+        let bar_height = (value * self.height as f32).round() as usize;
+        if !in_gap && y >= self.height - bar_height {
+            Some(self.bar_color)
+        } else {
+            self.background
+        }
+    }
+}
+
+impl Blittable<u32> for BarGraph {
+    fn blit_impl(&self, buffer: &mut [u32], buffer_width: usize, self_rect: Rect, dst_rect: Rect) {
+        let span_length = (
+            self_rect.x_range.end - self_rect.x_range.start
+        ).min(
+            dst_rect.x_range.end - dst_rect.x_range.start
+        );
+        let span_count = (
+            self_rect.y_range.end - self_rect.y_range.start
+        ).min(
+            dst_rect.y_range.end - dst_rect.y_range.start
+        );
+        for row in 0..span_count {
+            let y = self_rect.y_range.start + row;
+            let dst_stride = (dst_rect.y_range.start + row) * buffer_width + dst_rect.x_range.start;
+            for column in 0..span_length {
+                if let Some(color) = self.pixel(self_rect.x_range.start + column, y) {
+                    buffer[dst_stride + column] = color;
+                }
+            }
+        }
+    }
+
+    fn get_width(&self) -> usize {
+        self.width
+    }
+
+    fn get_height(&self) -> usize {
+        self.height
+    }
+}
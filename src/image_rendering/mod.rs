@@ -2,4 +2,5 @@ pub mod blittable;
 pub mod bresenham;
 
 pub mod bmp_sprite_decorators;
-pub mod ingame_sprite_decorators;
\ No newline at end of file
+pub mod ingame_sprite_decorators;
+pub mod bar_graph;
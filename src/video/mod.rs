@@ -1,7 +1,13 @@
+use std::cell::RefCell;
 use std::io::Cursor;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use rom_loaders_rs::multimedia::{SmackerFile, Audio};
-use crate::audio::{SoundMixer, Sound, PlaybackBuilder};
-use crate::audio::mixer::PlaybackStyle;
+use crate::audio::{SoundMixer, Sound, SoundId, PlaybackBuilder, BusId};
+use crate::audio::mixer::{PlaybackStyle, Volume};
+use crate::audio::volume::VolumeError;
+use crate::audio::source::{SoundSource, SourceState, SampleSource, StreamSource};
 use crate::image_rendering::blittable::{Blittable, Rect};
 
 #[derive(Copy, Clone, PartialEq)]
@@ -38,6 +44,79 @@ pub enum PlayerState {
     FinishedPlaying
 }
 
+/// How far the audio thread got into the track the picture follows
+#[derive(Default)]
+struct TrackPosition {
+    /// frames from the start of the track, also for voices restarted by a seek
+    frame: AtomicUsize,
+    started: AtomicBool,
+    /// a streamed track waiting for the decoder
+    starving: AtomicBool,
+    finished: AtomicBool
+}
+
+/// publishes the position of a video's audio track as the mixer pulls frames out of it
+struct TrackingSource<S> {
+    inner: S,
+    position: Arc<TrackPosition>
+}
+impl<S: SoundSource> SoundSource for TrackingSource<S> {
+    fn sample_rate(&self) -> f32 {
+        self.inner.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> SourceState {
+        let state = self.inner.next_frame(frame);
+        match state {
+            SourceState::Ready => {
+                self.position.frame.store(self.inner.position().unwrap_or(0), Ordering::Relaxed);
+                self.position.started.store(true, Ordering::Release);
+                self.position.starving.store(false, Ordering::Release);
+            }
+            SourceState::Finished => self.position.finished.store(true, Ordering::Release),
+            SourceState::Starving => self.position.starving.store(true, Ordering::Release)
+        }
+        state
+    }
+
+    fn stream_content(&mut self, content: Vec<f32>) {
+        self.inner.stream_content(content)
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.inner.position()
+    }
+
+    fn playback_style(&self) -> Option<PlaybackStyle> {
+        self.inner.playback_style()
+    }
+}
+
+/// The mixer a video's audio plays through
+enum VideoMixer {
+    /// opened by the player for itself, with its own output device
+    Owned(Box<SoundMixer>),
+    /// the game's mixer, so the audio follows its master volume and buses
+    Shared(Rc<RefCell<SoundMixer>>)
+}
+
+struct VideoAudio {
+    mixer: VideoMixer,
+    bus: BusId
+}
+
+/// Audio decoded alongside the picture instead of preloaded
+struct AudioStream {
+    /// next frame whose audio gets decoded
+    next_frame: usize,
+    /// frames of audio kept decoded ahead of the picture
+    lead_frames: usize
+}
+
 pub struct SmackerPlayer {
     pub state: PlayerState,
     pub frame_width: usize,
@@ -45,7 +124,18 @@ pub struct SmackerPlayer {
     fade_in_ms: usize,
     fade_out_ms: usize,
     smacker_file: SmackerFile,
-    sound_mixer: SoundMixer,
+    /// mixer and bus the audio tracks play on, `None` when they are never decoded
+    audio: Option<VideoAudio>,
+    audio_volume: Volume,
+    /// how far the fade-out turned the audio down
+    audio_fade: f32,
+    /// with their track index, decoded once the audio is preloaded and kept to restart the voices after a seek
+    tracks: Vec<(usize, Arc<Sound>)>,
+    voices: Vec<(usize, SoundId)>,
+    stream: Option<AudioStream>,
+    /// position and sample rate of the track the frame timing follows
+    sync_track: Option<(Arc<TrackPosition>, f32)>,
+    paused: bool,
     brightness: u8
 }
 impl Blittable<u32> for SmackerPlayer {
@@ -114,30 +204,310 @@ impl Blittable<u32> for SmackerPlayer {
 }
 
 impl SmackerPlayer {
+    /// Plays the audio through a mixer of its own
     pub fn load_from_stream(stream: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let mixer = VideoMixer::Owned(Box::new(SoundMixer::new()));
+        Self::load(stream, Some(VideoAudio { mixer, bus: BusId::MASTER }))
+    }
+    /// Plays the audio through the game's mixer on `bus`, e.g. a "cutscenes" bus with its own volume slider.
+    ///
+    /// The mixer is borrowed while the player's methods run, not in between.
+    pub fn load_with_mixer(stream: &mut Cursor<&[u8]>, mixer: Rc<RefCell<SoundMixer>>, bus: BusId) -> std::io::Result<Self> {
+        let mixer = VideoMixer::Shared(mixer);
+        Self::load(stream, Some(VideoAudio { mixer, bus }))
+    }
+    /// Plays the picture only, the audio tracks are never decoded
+    pub fn load_without_audio(stream: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        Self::load(stream, None)
+    }
+    fn load(stream: &mut Cursor<&[u8]>, audio: Option<VideoAudio>) -> std::io::Result<Self> {
         let smacker_file = SmackerFile::load(stream)?;
-        let sound_mixer = SoundMixer::new();
+        let state = match audio {
+            None => PreloadingAudioState::Complete,
+            Some(_) => PreloadingAudioState::InProgress
+        };
         Ok(Self {
             fade_in_ms: 0,
             fade_out_ms: 0,
             state: PlayerState::PreloadingAudio{
                 frame: 0,
-                state: PreloadingAudioState::InProgress
+                state
             },
             frame_width: smacker_file.file_info.width as usize,
             frame_height: smacker_file.file_info.height as usize,
             smacker_file,
-            sound_mixer,
+            audio,
+            audio_volume: Volume(1.0),
+            audio_fade: 1.0,
+            tracks: Vec::new(),
+            voices: Vec::new(),
+            stream: None,
+            sync_track: None,
+            paused: false,
             brightness: 0
         })
     }
+    /// Runs `f` with the mixer the audio plays through, `None` for a video without audio
+    fn with_mixer<R>(&mut self, f: impl FnOnce(&mut Self, &mut SoundMixer, BusId) -> R) -> Option<R> {
+        let mut audio = self.audio.take()?;
+        let result = match &mut audio.mixer {
+            VideoMixer::Owned(mixer) => f(self, mixer, audio.bus),
+            VideoMixer::Shared(mixer) => f(self, &mut mixer.borrow_mut(), audio.bus)
+        };
+        self.audio = Some(audio);
+        Some(result)
+    }
+    /// Starts the audio tracks at the time `frame` is shown
+    fn start_audio(&mut self, mixer: &mut SoundMixer, bus: BusId, frame: usize) {
+        if self.tracks.is_empty() {
+            let file_info = &self.smacker_file.file_info;
+            let streamed = self.stream.is_some();
+            self.tracks = (0..file_info.audio_flags.len())
+                .filter(|&i| file_info.audio_flags[i].contains(Audio::PRESENT))
+                .map(|i| (i, Arc::new(Sound {
+                    sample_rate: file_info.audio_rate[i] as f32,
+                    channels: if file_info.audio_flags[i].contains(Audio::IS_STEREO) {
+                        2
+                    } else {
+                        1
+                    },
+                    samples: if streamed {
+                        Vec::new()
+                    } else {
+                        file_info.audio_tracks[i].clone()
+                    },
+                    playback_style: if streamed {
+                        PlaybackStyle::Streamed
+                    } else {
+                        PlaybackStyle::Once
+                    }
+                })))
+                .collect();
+        }
+        if let Some(stream) = &mut self.stream {
+            // a streamed voice starts empty, with the audio of the frame shown
+            stream.next_frame = frame;
+        }
+        let seconds = frame as f32 * self.smacker_file.file_info.frame_interval / 1000.0;
+        for (track, sound) in self.tracks.iter() {
+            let start = (seconds * sound.sample_rate) as usize;
+            let position = Arc::new(TrackPosition::default());
+            let builder = if self.stream.is_some() {
+                let inner = StreamSource::new(sound.sample_rate, sound.channels).with_position(start);
+                PlaybackBuilder::new().with_source(TrackingSource { inner, position: position.clone() })
+            } else {
+                let mut inner = SampleSource::shared(sound.clone());
+                inner.seek(start);
+                PlaybackBuilder::new().with_source(TrackingSource { inner, position: position.clone() })
+            };
+            let volume = Volume::clamped(self.audio_volume.0 * self.audio_fade);
+            if let Some(sound_id) = mixer.play(builder.with_bus(bus).with_volume(volume)) {
+                if self.paused {
+                    mixer.pause(sound_id);
+                }
+                self.voices.push((*track, sound_id));
+                if self.sync_track.is_none() {
+                    self.sync_track = Some((position, sound.sample_rate));
+                }
+            }
+        }
+    }
+    /// Decodes the audio up to the stream's lead past `frame` and hands it to the voices
+    fn stream_audio(&mut self, mixer: &mut SoundMixer, frame: usize) -> std::io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(())
+        };
+        let end = (frame + stream.lead_frames).min(self.smacker_file.file_info.frames.len());
+        if stream.next_frame >= end {
+            return Ok(());
+        }
+        while stream.next_frame < end {
+            self.smacker_file.unpack(stream.next_frame, true, false)?;
+            stream.next_frame += 1;
+        }
+        let audio_tracks = &mut self.smacker_file.file_info.audio_tracks;
+        for &(track, sound_id) in self.voices.iter() {
+            let samples = std::mem::take(&mut audio_tracks[track]);
+            if !samples.is_empty() {
+                mixer.stream_sound(sound_id, samples);
+            }
+        }
+        // tracks without a voice are dropped rather than piling up
+        for samples in audio_tracks.iter_mut() {
+            samples.clear();
+        }
+        Ok(())
+    }
+    fn stop_audio(&mut self, mixer: &mut SoundMixer) {
+        self.sync_track = None;
+        for (_, sound_id) in self.voices.drain(..) {
+            mixer.stop(sound_id);
+        }
+    }
+    /// Milliseconds into the video the player is hearing right now, `None` while the audio
+    /// hasn't started yet, is waiting for the decoder, has already ended or there is none
+    fn audio_position_ms(&self, mixer: &SoundMixer) -> Option<f32> {
+        let (position, sample_rate) = match &self.sync_track {
+            Some((position, sample_rate)) if position.started.load(Ordering::Acquire) &&
+                !position.starving.load(Ordering::Acquire) &&
+                !position.finished.load(Ordering::Acquire) => (position.frame.load(Ordering::Relaxed), *sample_rate),
+            _ => return None
+        };
+        // the voice's position is where the mixer is, the player hears what was mixed a little earlier
+        let clock = mixer.clock();
+        let in_flight = mixer.current_frame().saturating_sub(clock.playback_frame()) as f32 / clock.sample_rate().max(1.0);
+        Some((position as f32 / sample_rate - in_flight).max(0.0) * 1000.0)
+    }
+    fn apply_audio_volume(&mut self, mixer: &mut SoundMixer, fade: f32) {
+        self.audio_fade = fade;
+        let volume = self.audio_volume.0 * fade;
+        for &(_, sound_id) in self.voices.iter() {
+            mixer.set_volume_clamped(sound_id, volume);
+        }
+    }
+    /// Volume of the video's audio, the fade-out turns it down from there
+    pub fn set_audio_volume(&mut self, volume: Volume) -> Result<(), VolumeError> {
+        self.audio_volume = volume.validate()?;
+        let fade = self.audio_fade;
+        self.with_mixer(|player, mixer, _| player.apply_audio_volume(mixer, fade));
+        Ok(())
+    }
+    pub fn audio_volume(&self) -> Volume {
+        self.audio_volume
+    }
+    pub fn frame_count(&self) -> usize {
+        self.smacker_file.file_info.frames.len()
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    /// Holds the picture and the audio, [`SmackerPlayer::frame`] does nothing until [`SmackerPlayer::resume`]
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.paused = true;
+        self.with_mixer(|player, mixer, _| for &(_, sound_id) in player.voices.iter() {
+            mixer.pause(sound_id);
+        });
+    }
+    pub fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        self.paused = false;
+        self.with_mixer(|player, mixer, _| for &(_, sound_id) in player.voices.iter() {
+            mixer.resume(sound_id);
+        });
+    }
+    /// Shows `frame` and restarts the audio from the same point.
+    ///
+    /// Frames are delta-coded, so seeking forward decodes every frame in between and seeking
+    /// backward decodes from the first frame. A finished video starts playing again.
+    /// Fails while the audio is still being preloaded or faded in.
+    pub fn seek(&mut self, frame: usize) -> std::io::Result<PlayerState> {
+        let next_frame = match self.state {
+            PlayerState::IsRendering { frame, .. } => frame,
+            PlayerState::FadeOut(_) | PlayerState::FinishedPlaying => self.frame_count(),
+            _ => return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "can't seek before the video has started playing"
+            ))
+        };
+        let frame_count = self.frame_count();
+        if frame_count == 0 {
+            return Ok(self.state);
+        }
+        let frame = frame.min(frame_count - 1);
+        // the last decoded frame is the only state we can continue from, anything earlier needs a restart
+        let first = if frame + 1 >= next_frame && next_frame > 0 { next_frame } else { 0 };
+        for i in first..=frame {
+            self.smacker_file.unpack(i, false, true)?;
+        }
+        self.brightness = 255;
+        self.state = PlayerState::IsRendering {
+            frame: frame + 1,
+            delta: 0.0,
+            state: RenderingFramesState::RenderedNewFrame
+        };
+        self.audio_fade = 1.0;
+        self.with_mixer(|player, mixer, bus| {
+            player.stop_audio(mixer);
+            player.start_audio(mixer, bus, frame);
+            player.stream_audio(mixer, frame)
+        }).transpose()?;
+        Ok(self.state)
+    }
+    /// Ends the video early, e.g. when the player presses Escape.
+    ///
+    /// The picture and the audio fade out over the fade-out time, starting from the current
+    /// brightness, and the audio voices are stopped once it is over.
+    pub fn skip(&mut self) -> PlayerState {
+        self.resume();
+        self.state = match self.state {
+            PlayerState::PreloadingAudio { .. } => {
+                self.brightness = 0;
+                PlayerState::FinishedPlaying
+            },
+            PlayerState::FadeIn(_) | PlayerState::IsRendering { .. } if self.fade_out_ms > 0 => {
+                let t_max = self.fade_out_ms as f32;
+                // the fade-out brightness goes as 1 - (t / t_max)^2, pick the t we are at already
+                let t = t_max * (1.0 - self.brightness as f32 / 255.0).sqrt();
+                PlayerState::FadeOut(FadeOutState::InProgress { t, t_max })
+            },
+            PlayerState::FadeIn(_) | PlayerState::IsRendering { .. } => PlayerState::FadeOut(FadeOutState::Complete),
+            state => state
+        };
+        self.state
+    }
+    /// Like [`SmackerPlayer::seek`], with the position in milliseconds
+    pub fn seek_ms(&mut self, ms: f32) -> std::io::Result<PlayerState> {
+        let frame = (ms.max(0.0) / self.smacker_file.file_info.frame_interval) as usize;
+        self.seek(frame)
+    }
+    /// Decodes the audio while the video plays, `lead_ms` ahead of the picture, instead of
+    /// preloading the whole soundtrack before the first frame.
+    /// Fails once the first [`SmackerPlayer::frame`] started preloading.
+    pub fn set_audio_streaming(&mut self, lead_ms: usize) -> std::io::Result<()> {
+        match self.state {
+            PlayerState::PreloadingAudio { frame: 0, .. } if self.tracks.is_empty() => {},
+            _ => return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "audio streaming must be chosen before the video starts"
+            ))
+        }
+        let frame_interval = self.smacker_file.file_info.frame_interval.max(1.0);
+        self.stream = Some(AudioStream {
+            next_frame: 0,
+            lead_frames: ((lead_ms as f32 / frame_interval).ceil() as usize).max(1)
+        });
+        if let PlayerState::PreloadingAudio { state, .. } = &mut self.state {
+            *state = PreloadingAudioState::Complete;
+        }
+        Ok(())
+    }
     pub fn set_fade_in_ms(&mut self, fade_in_ms: usize) {
         self.fade_in_ms = fade_in_ms;
     }
     pub fn set_fade_out_ms(&mut self, fade_out_ms: usize) {
         self.fade_out_ms = fade_out_ms;
     }
+    /// Advances the video by `delta_time` milliseconds.
+    ///
+    /// While the video's audio is playing, its position decides which frame is shown instead:
+    /// frames are skipped when the picture falls behind and held when it runs ahead.
     pub fn frame(&mut self, delta_time: f32) -> std::io::Result<PlayerState> {
+        if self.paused {
+            return Ok(self.state);
+        }
+        let audio_ms = match self.state {
+            PlayerState::IsRendering { frame, .. } => self.with_mixer(|player, mixer, _| {
+                player.stream_audio(mixer, frame)?;
+                Ok::<_, std::io::Error>(player.audio_position_ms(mixer))
+            }).transpose()?.flatten(),
+            _ => None
+        };
         match &mut self.state {
             PlayerState::FinishedPlaying => Ok(self.state),
             PlayerState::PreloadingAudio { frame, state }  => match state {
@@ -184,22 +554,10 @@ impl SmackerPlayer {
                         delta: 0.0,
                         state: RenderingFramesState::RenderedNewFrame // we need to renderize frame immediately
                     };
-                    for i in 0..self.smacker_file.file_info.audio_flags.len() {
-                        if !self.smacker_file.file_info.audio_flags[i].contains(Audio::PRESENT) {
-                            continue;
-                        }
-                        let sound = Sound {
-                            sample_rate: self.smacker_file.file_info.audio_rate[i] as f32,
-                            channels: if self.smacker_file.file_info.audio_flags[i].contains(Audio::IS_STEREO) {
-                                2
-                            } else {
-                                1
-                            },
-                            samples: self.smacker_file.file_info.audio_tracks[i].clone(),
-                            playback_style: PlaybackStyle::Once
-                        };
-                        self.sound_mixer.play(PlaybackBuilder::new().with_sound(sound)).unwrap();
-                    }
+                    self.with_mixer(|player, mixer, bus| {
+                        player.start_audio(mixer, bus, 0);
+                        player.stream_audio(mixer, 0)
+                    }).transpose()?;
                     Ok(self.state)
                 },
             },
@@ -215,7 +573,11 @@ impl SmackerPlayer {
                     };
                     return Ok(self.state);
                 }
-                *delta += delta_time;
+                match audio_ms {
+                    // time since the frame on screen was due
+                    Some(ms) => *delta = ms - (*frame - 1) as f32 * self.smacker_file.file_info.frame_interval,
+                    None => *delta += delta_time
+                }
                 *state = if *frame == self.smacker_file.file_info.frames.len() {
                     RenderingFramesState::Complete
                 } else if *delta < self.smacker_file.file_info.frame_interval {
@@ -241,15 +603,29 @@ impl SmackerPlayer {
                         let alpha = alpha * alpha;
                         self.brightness = ((1.0 - alpha) * 255.0) as u8;
                         *t += delta_time;
+                        self.with_mixer(|player, mixer, _| player.apply_audio_volume(mixer, 1.0 - alpha));
                     }
                     Ok(self.state)
                 },
                 FadeOutState::Complete => {
                     self.brightness = 0;
+                    self.with_mixer(|player, mixer, _| player.stop_audio(mixer));
                     self.state = PlayerState::FinishedPlaying;
                     Ok(self.state)
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+impl Drop for SmackerPlayer {
+    fn drop(&mut self) {
+        // voices on a shared mixer would outlive the player
+        if let Some(VideoAudio { mixer: VideoMixer::Shared(mixer), .. }) = &self.audio {
+            if let Ok(mut mixer) = mixer.try_borrow_mut() {
+                for &(_, sound_id) in self.voices.iter() {
+                    mixer.stop(sound_id);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Sound container tests, variants are picked with fixed seeds.
+use rom_media_rs::audio::{Sound, SoundContainer, PickMode, PitchError, VolumeError};
+use rom_media_rs::audio::mixer::PlaybackStyle;
+
+fn container(variants: usize) -> SoundContainer {
+    let sounds = (0..variants)
+        .map(|i| Sound {
+            sample_rate: 44100.0,
+            channels: 1,
+            samples: vec![i as f32 / variants as f32; 4],
+            playback_style: PlaybackStyle::Once
+        })
+        .collect();
+    SoundContainer::new(sounds)
+}
+
+fn picks(container: &mut SoundContainer, count: usize) -> Vec<usize> {
+    (0..count).map(|_| container.pick().unwrap()).collect()
+}
+
+fn is_permutation(picks: &[usize]) -> bool {
+    let mut sorted = picks.to_vec();
+    sorted.sort_unstable();
+    sorted == (0..picks.len()).collect::<Vec<_>>()
+}
+
+#[test]
+fn empty_containers_pick_nothing() {
+    let mut empty = container(0);
+    assert!(empty.is_empty());
+    assert_eq!(empty.pick(), None);
+    assert!(empty.builder().is_none());
+}
+
+#[test]
+fn sequential_plays_in_order() {
+    let mut sequential = container(3).with_pick_mode(PickMode::Sequential);
+    assert_eq!(picks(&mut sequential, 7), [0, 1, 2, 0, 1, 2, 0]);
+}
+
+#[test]
+fn random_never_repeats_the_last_variant() {
+    for seed in 1..20 {
+        let mut random = container(3).with_seed(seed);
+        let picks = picks(&mut random, 100);
+        assert!(picks.windows(2).all(|pair| pair[0] != pair[1]), "{:?}", picks);
+        assert!((0..3).all(|variant| picks.contains(&variant)));
+    }
+    let mut single = container(1).with_seed(1);
+    assert_eq!(picks(&mut single, 3), [0, 0, 0]);
+}
+
+#[test]
+fn shuffled_plays_every_variant_once_per_round() {
+    for seed in 1..20 {
+        let mut shuffled = container(5).with_pick_mode(PickMode::Shuffled).with_seed(seed);
+        let picks = picks(&mut shuffled, 50);
+        assert!(picks.chunks(5).all(is_permutation), "{:?}", picks);
+        // nor across the reshuffle
+        assert!(picks.windows(2).all(|pair| pair[0] != pair[1]), "{:?}", picks);
+    }
+}
+
+#[test]
+fn changing_the_pick_mode_starts_a_new_round() {
+    for seed in 1..20 {
+        let mut container = container(5).with_pick_mode(PickMode::Sequential).with_seed(seed);
+        assert_eq!(picks(&mut container, 2), [0, 1]);
+        let mut container = container.with_pick_mode(PickMode::Shuffled);
+        let picks = picks(&mut container, 10);
+        assert!(picks.chunks(5).all(is_permutation), "{:?}", picks);
+
+        let mut container = container.with_pick_mode(PickMode::Sequential);
+        assert_eq!(container.pick(), Some(0));
+    }
+}
+
+#[test]
+fn ranges_are_validated() {
+    assert!(container(2).with_volume_range(0.25, 0.75).is_ok());
+    assert_eq!(container(2).with_volume_range(0.5, 1.5).err(), Some(VolumeError::VolumeOutOfRange(1.5)));
+    assert_eq!(container(2).with_volume_range(f32::NAN, 1.0).err(), Some(VolumeError::NotFinite));
+
+    assert!(container(2).with_pitch_range(1.5, 0.5).is_ok());
+    assert_eq!(container(2).with_pitch_range(0.0, 1.0).err(), Some(PitchError::OutOfRange(0.0)));
+    assert_eq!(container(2).with_pitch_range(1.0, -2.0).err(), Some(PitchError::OutOfRange(-2.0)));
+    assert_eq!(container(2).with_pitch_range(1.0, f32::INFINITY).err(), Some(PitchError::NotFinite));
+}
@@ -0,0 +1,100 @@
+//! Sound editing tests on generated tones.
+use std::f32::consts::PI;
+use rom_media_rs::audio::Sound;
+use rom_media_rs::audio::mixer::PlaybackStyle;
+
+fn sine(frequency: f32, sample_rate: f32, frames: usize) -> Sound {
+    Sound {
+        sample_rate,
+        channels: 1,
+        samples: (0..frames).map(|i| 0.5 * (2.0 * PI * frequency * i as f32 / sample_rate).sin()).collect(),
+        playback_style: PlaybackStyle::Looped
+    }
+}
+
+/// largest difference, ignoring the edges where the resampling kernel is cut short
+fn max_error(actual: &Sound, expected: &Sound) -> f32 {
+    let end = actual.samples.len().min(expected.samples.len()) - 100;
+    actual.samples[100..end].iter()
+        .zip(expected.samples[100..end].iter())
+        .map(|(a, e)| (a - e).abs())
+        .fold(0.0, f32::max)
+}
+
+#[test]
+fn channel_conversion() {
+    let mono = sine(440.0, 22050.0, 100);
+    let stereo = mono.to_stereo();
+    assert_eq!(stereo.channels, 2);
+    assert_eq!(stereo.frame_count(), 100);
+    assert_eq!(stereo.samples[6], stereo.samples[7]);
+    assert_eq!(stereo.to_mono().samples, mono.samples);
+    assert_eq!(stereo.to_stereo().samples, stereo.samples);
+
+    let wide = Sound { channels: 2, samples: vec![1.0, 0.0, -0.5, 0.5], ..mono.clone() };
+    assert_eq!(wide.to_mono().samples, [0.5, 0.0]);
+    assert_eq!(wide.to_mono().playback_style, PlaybackStyle::Looped);
+}
+
+#[test]
+fn trim_silence_keeps_the_audible_part() {
+    let tone = sine(440.0, 22050.0, 1000);
+    let mut samples = vec![0.0; 100];
+    samples.extend(tone.samples.iter());
+    samples.extend(vec![0.0001; 50]);
+    let padded = Sound { samples, ..tone.clone() };
+    // a sine starts and ends near zero, those frames go along with the padding
+    let trimmed = padded.trim_silence(-60.0);
+    assert!(trimmed.frame_count() <= 1000 && trimmed.frame_count() > 990, "{}", trimmed.frame_count());
+
+    assert_eq!(Sound { samples: vec![0.0; 10], ..tone }.trim_silence(-60.0).frame_count(), 0);
+}
+
+#[test]
+fn split_and_concatenate() {
+    let tone = sine(440.0, 22050.0, 22050).to_stereo();
+    let pieces = tone.split(&[100, 0, 100, 30000, 5000]);
+    assert_eq!(pieces.iter().map(Sound::frame_count).collect::<Vec<_>>(), [100, 4900, 17050]);
+    assert_eq!(Sound::concatenate(&pieces).unwrap().samples, tone.samples);
+
+    // later clips take the channels and rate of the first
+    let joined = Sound::concatenate(&[tone.clone(), sine(440.0, 11025.0, 11025)]).unwrap();
+    assert_eq!(joined.channels, 2);
+    assert_eq!(joined.sample_rate, 22050.0);
+    assert_eq!(joined.frame_count(), 44100);
+    assert_eq!(joined.playback_style, PlaybackStyle::Looped);
+
+    assert!(Sound::concatenate(&[]).is_err());
+    let broken = Sound { sample_rate: 0.0, ..tone.clone() };
+    assert!(Sound::concatenate(&[tone, broken]).is_err());
+}
+
+#[test]
+fn resampling_keeps_the_tone() {
+    let low = sine(440.0, 22050.0, 22050);
+    let high = sine(440.0, 44100.0, 44100);
+
+    let up = low.resampled(44100.0).unwrap();
+    assert_eq!(up.sample_rate, 44100.0);
+    assert_eq!(up.frame_count(), 44100);
+    assert!(max_error(&up, &high) < 0.01);
+
+    let down = high.resampled(22050.0).unwrap();
+    assert_eq!(down.sample_rate, 22050.0);
+    assert_eq!(down.frame_count(), 22050);
+    assert!(max_error(&down, &low) < 0.01);
+
+    let odd = low.to_stereo().resampled(32000.0).unwrap();
+    assert_eq!(odd.channels, 2);
+    assert_eq!(odd.frame_count(), 32000);
+}
+
+#[test]
+fn resampling_rejects_invalid_rates() {
+    let tone = sine(440.0, 22050.0, 100);
+    for rate in [0.0, -44100.0, f32::NAN, f32::INFINITY] {
+        assert!(tone.resampled(rate).is_err(), "{}", rate);
+    }
+    assert!(Sound { sample_rate: 0.0, ..tone.clone() }.resampled(44100.0).is_err());
+    assert_eq!(tone.resampled(22050.0).unwrap().samples, tone.samples);
+}
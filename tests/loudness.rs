@@ -0,0 +1,74 @@
+//! Loudness measurement tests against the reference levels of EBU Tech 3341.
+use std::f32::consts::PI;
+use rom_media_rs::audio::{Sound, SoundBank, SoundAsset, Normalization};
+use rom_media_rs::audio::mixer::PlaybackStyle;
+
+/// sine of the given peak in dBFS, the same on every channel
+fn sine(frequency: f32, peak_db: f32, sample_rate: f32, seconds: f32, channels: u16) -> Sound {
+    let amplitude = 10f32.powf(peak_db / 20.0);
+    let frames = (sample_rate * seconds) as usize;
+    let samples = (0..frames)
+        .flat_map(|i| {
+            let value = amplitude * (2.0 * PI * frequency * i as f32 / sample_rate).sin();
+            vec![value; channels as usize]
+        })
+        .collect();
+    Sound { sample_rate, channels, samples, playback_style: PlaybackStyle::Once }
+}
+
+fn assert_lufs(sound: &Sound, expected: f32) {
+    let measured = sound.loudness().integrated_lufs;
+    assert!((measured - expected).abs() < 0.1, "{} LUFS instead of {}", measured, expected);
+}
+
+#[test]
+fn sine_at_minus_20_dbfs_measures_minus_20_lufs() {
+    assert_lufs(&sine(1000.0, -20.0, 48000.0, 5.0, 2), -20.0);
+    assert_lufs(&sine(1000.0, -20.0, 44100.0, 5.0, 2), -20.0);
+    // a single channel carries half the power
+    assert_lufs(&sine(1000.0, -20.0, 44100.0, 5.0, 1), -23.0);
+
+    let loudness = sine(1000.0, -20.0, 48000.0, 1.0, 2).loudness();
+    assert!((loudness.sample_peak_db() + 20.0).abs() < 0.01);
+}
+
+#[test]
+fn gating_ignores_silence() {
+    // twice as much silence as tone would average to about -27.8 LUFS,
+    // only the blocks straddling the end of the tone still count
+    let mut padded = sine(1000.0, -20.0, 44100.0, 2.0, 1);
+    padded.samples.extend(vec![0.0; 44100 * 4]);
+    let measured = padded.loudness().integrated_lufs;
+    assert!((measured + 23.0).abs() < 0.5, "{}", measured);
+
+    let silence = sine(1000.0, -100.0, 44100.0, 1.0, 1).loudness();
+    assert_eq!(silence.integrated_lufs, f32::NEG_INFINITY);
+    assert_eq!(silence.normalization_gain(-16.0, -1.0).0, 1.0);
+}
+
+#[test]
+fn clips_shorter_than_a_block_are_one_block() {
+    let short = sine(1000.0, -20.0, 44100.0, 0.1, 1).loudness();
+    assert!((short.integrated_lufs + 23.0).abs() < 0.5, "{}", short.integrated_lufs);
+    assert_eq!(sine(1000.0, -80.0, 44100.0, 0.1, 1).loudness().integrated_lufs, f32::NEG_INFINITY);
+}
+
+#[test]
+fn normalization_reaches_the_target() {
+    let mut tone = sine(1000.0, -20.0, 44100.0, 2.0, 1);
+    tone.normalize(-16.0, -1.0);
+    assert_lufs(&tone, -16.0);
+
+    // the ceiling wins over the target
+    let mut tone = sine(1000.0, -20.0, 44100.0, 2.0, 1);
+    tone.normalize(0.0, -6.0);
+    assert!((tone.loudness().sample_peak_db() + 6.0).abs() < 0.01);
+
+    let mut wav = Vec::new();
+    sine(1000.0, -20.0, 44100.0, 1.0, 1).write_wav(&mut wav).unwrap();
+    let mut bank = SoundBank::new();
+    bank.register("at_load", SoundAsset::from_bytes(wav.clone()).with_normalization(Normalization::AtLoad(-16.0)));
+    bank.register("at_play", SoundAsset::from_bytes(wav).with_normalization(Normalization::AtPlay(-16.0)));
+    assert_lufs(&bank.sound("at_load").unwrap(), -16.0);
+    assert_lufs(&bank.sound("at_play").unwrap(), -23.0);
+}
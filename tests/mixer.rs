@@ -0,0 +1,488 @@
+//! Offline mixer tests, everything is rendered through [`SoundMixer::offline`] without an output device.
+use rom_media_rs::audio::{SoundMixer, Sound, PlaybackBuilder, StreamSource, Gain, VolumeCurve, samples_checksum};
+use rom_media_rs::audio::{BusId, EqBand, Compressor, EffectError, AudioConfig, RenderAhead, Ducking, DuckTrigger};
+use rom_media_rs::audio::{SoundBank, SoundAsset, SampleSource, PitchedSource, MusicPlayer, MusicTrack, MusicTransition};
+use rom_media_rs::audio::{MixerSnapshot, VoiceSnapshot, MusicSnapshot, Oscillator, Waveform};
+use rom_media_rs::audio::meter::normalize_db;
+use rom_media_rs::audio::mixer::{PlaybackStyle, Volume};
+
+const SAMPLE_RATE: f32 = 44100.0;
+
+fn sound(sample_rate: f32, channels: u16, samples: &[f32], playback_style: PlaybackStyle) -> Sound {
+    Sound {
+        sample_rate,
+        channels,
+        samples: samples.to_vec(),
+        playback_style
+    }
+}
+
+fn mono(samples: &[f32], playback_style: PlaybackStyle) -> Sound {
+    sound(SAMPLE_RATE, 1, samples, playback_style)
+}
+
+fn assert_samples(actual: &[f32], expected: &[f32]) {
+    assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+    for (a, e) in actual.iter().zip(expected.iter()) {
+        assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn silence_without_voices() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    assert_samples(&mixer.render(4), &[0.0; 8]);
+    assert_eq!(mixer.current_frame(), 4);
+}
+
+#[test]
+fn mono_plays_on_both_ears() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.1, 0.2, 0.3], PlaybackStyle::Once)));
+    assert_samples(&mixer.render(4), &[0.1, 0.1, 0.2, 0.2, 0.3, 0.3, 0.0, 0.0]);
+}
+
+#[test]
+fn stereo_keeps_channels_apart() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let stereo = sound(SAMPLE_RATE, 2, &[0.1, -0.1, 0.2, -0.2], PlaybackStyle::Once);
+    mixer.play(PlaybackBuilder::new().with_sound(stereo));
+    assert_samples(&mixer.render(3), &[0.1, -0.1, 0.2, -0.2, 0.0, 0.0]);
+}
+
+#[test]
+fn rendering_in_pieces_matches_one_render() {
+    let mut whole = SoundMixer::offline(SAMPLE_RATE);
+    let mut pieces = SoundMixer::offline(SAMPLE_RATE);
+    let stereo = sound(SAMPLE_RATE, 2, &[0.1, -0.1, 0.2, -0.2, 0.3, -0.3], PlaybackStyle::Looped);
+    whole.play(PlaybackBuilder::new().with_sound(stereo.clone()));
+    pieces.play(PlaybackBuilder::new().with_sound(stereo));
+
+    let expected = whole.render(10);
+    let mut actual = pieces.render(1);
+    actual.extend(pieces.render(6));
+    actual.extend(pieces.render(3));
+    assert_eq!(samples_checksum(&actual), samples_checksum(&expected));
+}
+
+#[test]
+fn voices_are_summed() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play_group(vec![
+        PlaybackBuilder::new().with_sound(mono(&[0.1, 0.1], PlaybackStyle::Once)),
+        PlaybackBuilder::new().with_sound(sound(SAMPLE_RATE, 2, &[0.2, 0.3, 0.2, 0.3], PlaybackStyle::Once))
+    ]);
+    assert_samples(&mixer.render(2), &[0.3, 0.4, 0.3, 0.4]);
+}
+
+#[test]
+fn once_is_culled_after_the_end() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.5, 0.5], PlaybackStyle::Once)));
+    assert_eq!(mixer.debug_info().unwrap().voices.len(), 1);
+    mixer.render(3);
+    let info = mixer.debug_info().unwrap();
+    assert!(info.voices.is_empty());
+    assert_eq!(info.stats.voices_culled, 1);
+}
+
+#[test]
+fn looped_wraps_around() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.1, 0.2, 0.3], PlaybackStyle::Looped)));
+    let output = mixer.render(7);
+    let left: Vec<f32> = output.iter().step_by(2).cloned().collect();
+    assert_samples(&left, &[0.1, 0.2, 0.3, 0.1, 0.2, 0.3, 0.1]);
+    let info = mixer.debug_info().unwrap();
+    assert_eq!(info.voices.len(), 1);
+    assert!(info.voices[0].looping);
+    assert_eq!(info.voices[0].playback_style, Some(PlaybackStyle::Looped));
+}
+
+#[test]
+fn streamed_waits_for_content() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let id = mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.1], PlaybackStyle::Streamed))).unwrap();
+    assert_samples(&mixer.render(2), &[0.1, 0.1, 0.0, 0.0]);
+    let info = mixer.debug_info().unwrap();
+    assert!(info.voices[0].starving);
+
+    mixer.stream_sound(id, vec![0.4, 0.5]);
+    assert_samples(&mixer.render(3), &[0.4, 0.4, 0.5, 0.5, 0.0, 0.0]);
+    // a streamed voice stays alive until it is stopped
+    assert_eq!(mixer.debug_info().unwrap().voices.len(), 1);
+    mixer.stop(id);
+    let info = mixer.debug_info().unwrap();
+    assert!(info.voices.is_empty());
+    assert_eq!(info.stats.voices_stopped, 1);
+}
+
+#[test]
+fn stream_source_counts_from_its_start_position() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    // e.g. a video's audio restarted by a seek, 1000 frames into the track
+    let source = StreamSource::new(SAMPLE_RATE, 2).with_position(1000);
+    let id = mixer.play(PlaybackBuilder::new().with_source(source)).unwrap();
+    assert_samples(&mixer.render(1), &[0.0, 0.0]);
+    let info = mixer.debug_info().unwrap();
+    assert!(info.voices[0].starving);
+    assert_eq!(info.voices[0].position, Some(1000));
+
+    mixer.stream_sound(id, vec![0.1, 0.2, 0.3, 0.4]);
+    assert_samples(&mixer.render(3), &[0.1, 0.2, 0.3, 0.4, 0.0, 0.0]);
+    assert_eq!(mixer.debug_info().unwrap().voices[0].position, Some(1002));
+
+    // played content is gone, new content picks up where the stream left off
+    mixer.stream_sound(id, vec![0.5, 0.6]);
+    assert_samples(&mixer.render(2), &[0.5, 0.6, 0.0, 0.0]);
+    assert_eq!(mixer.debug_info().unwrap().voices[0].position, Some(1003));
+}
+
+#[test]
+fn lower_sample_rates_hold_frames() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let half_rate = sound(SAMPLE_RATE / 2.0, 1, &[0.1, 0.2], PlaybackStyle::Once);
+    mixer.play(PlaybackBuilder::new().with_sound(half_rate));
+    let output = mixer.render(5);
+    let left: Vec<f32> = output.iter().step_by(2).cloned().collect();
+    assert_samples(&left, &[0.1, 0.1, 0.2, 0.2, 0.0]);
+}
+
+#[test]
+fn higher_sample_rates_skip_frames() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let double_rate = sound(SAMPLE_RATE * 2.0, 1, &[0.1, 0.2, 0.3, 0.4], PlaybackStyle::Once);
+    mixer.play(PlaybackBuilder::new().with_sound(double_rate));
+    let output = mixer.render(3);
+    let left: Vec<f32> = output.iter().step_by(2).cloned().collect();
+    assert_samples(&left, &[0.1, 0.3, 0.0]);
+}
+
+#[test]
+fn gain_and_volume_scale_the_voice() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play(PlaybackBuilder::new()
+        .with_sound(mono(&[0.8], PlaybackStyle::Looped))
+        .with_gain(Gain(0.5)));
+    assert_samples(&mixer.render(1), &[0.4, 0.4]);
+
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play(PlaybackBuilder::new()
+        .with_sound(mono(&[0.8], PlaybackStyle::Looped))
+        .with_volume(Volume(0.5)));
+    // the default curve squares the slider position
+    assert_samples(&mixer.render(1), &[0.2, 0.2]);
+
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.set_volume_curve(VolumeCurve::Linear);
+    mixer.play(PlaybackBuilder::new()
+        .with_sound(mono(&[0.8], PlaybackStyle::Looped))
+        .with_volume(Volume(0.5)));
+    assert_samples(&mixer.render(1), &[0.4, 0.4]);
+}
+
+#[test]
+fn volume_changes_apply_to_following_frames() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let id = mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.8], PlaybackStyle::Looped))).unwrap();
+    assert_samples(&mixer.render(1), &[0.8, 0.8]);
+    mixer.set_gain(id, Gain(0.25)).unwrap();
+    assert_samples(&mixer.render(1), &[0.2, 0.2]);
+    mixer.set_master_gain(Gain(0.5)).unwrap();
+    assert_samples(&mixer.render(1), &[0.1, 0.1]);
+
+    let info = mixer.debug_info().unwrap();
+    assert!((info.voices[0].gain - 0.25).abs() < 1e-6);
+    assert!((info.voices[0].effective_gain - 0.125).abs() < 1e-6);
+}
+
+#[test]
+fn invalid_volumes_are_rejected() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let rejected = mixer.play(PlaybackBuilder::new()
+        .with_sound(mono(&[0.8], PlaybackStyle::Looped))
+        .with_volume(Volume(1.5)));
+    assert!(rejected.is_none());
+    assert!(mixer.play(PlaybackBuilder::new()).is_none());
+    assert_eq!(mixer.stats().plays_rejected, 2);
+
+    let id = mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.8], PlaybackStyle::Looped))).unwrap();
+    assert!(mixer.set_gain(id, Gain(f32::NAN)).is_err());
+    assert!(mixer.set_volume_self(Volume(-0.1)).is_err());
+    assert_samples(&mixer.render(1), &[0.8, 0.8]);
+}
+
+#[test]
+fn unplayable_sources_are_rejected() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    assert!(mixer.play(PlaybackBuilder::new().with_sound(sound(SAMPLE_RATE, 3, &[0.1; 3], PlaybackStyle::Once))).is_none());
+    assert!(mixer.play(PlaybackBuilder::new().with_sound(sound(0.0, 1, &[0.1], PlaybackStyle::Once))).is_none());
+    assert!(mixer.play(PlaybackBuilder::new().with_sound(sound(f32::NAN, 1, &[0.1], PlaybackStyle::Once))).is_none());
+    assert_eq!(mixer.stats().plays_rejected, 3);
+
+    // content for a voice which isn't streamed is dropped
+    let id = mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.2], PlaybackStyle::Once))).unwrap();
+    mixer.stream_sound(id, vec![0.9, 0.9]);
+    assert_samples(&mixer.render(3), &[0.2, 0.2, 0.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn paused_voices_hold_their_position() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let id = mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.1, 0.2, 0.3], PlaybackStyle::Once))).unwrap();
+    assert_samples(&mixer.render(1), &[0.1, 0.1]);
+    mixer.pause(id);
+    assert_samples(&mixer.render(2), &[0.0; 4]);
+    mixer.resume(id);
+    assert_samples(&mixer.render(2), &[0.2, 0.2, 0.3, 0.3]);
+}
+
+#[test]
+fn scheduled_voices_start_on_their_frame() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.play(PlaybackBuilder::new()
+        .with_sound(mono(&[0.5], PlaybackStyle::Once))
+        .with_start_frame(2));
+    assert_eq!(mixer.debug_info().unwrap().voices[0].scheduled_frame, Some(2));
+    let output = mixer.render(4);
+    let left: Vec<f32> = output.iter().step_by(2).cloned().collect();
+    assert_samples(&left, &[0.0, 0.0, 0.5, 0.0]);
+}
+
+#[test]
+fn identical_runs_render_identical_output() {
+    let render = || {
+        let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+        let samples: Vec<f32> = (0..1000).map(|i| ((i as f32) * 0.05).sin() * 0.5).collect();
+        mixer.play(PlaybackBuilder::new().with_sound(mono(&samples, PlaybackStyle::Looped)).with_gain(Gain(0.7)));
+        mixer.play(PlaybackBuilder::new().with_sound(sound(22050.0, 2, &samples, PlaybackStyle::Once)));
+        samples_checksum(&mixer.render(4096))
+    };
+    assert_eq!(render(), render());
+}
+
+#[test]
+fn invalid_effect_parameters_are_rejected() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    assert_eq!(mixer.set_bus_eq(BusId::MASTER, vec![EqBand::peaking(f32::NAN, 1.0, 3.0)]), Err(EffectError::NotFinite));
+    assert_eq!(mixer.set_bus_eq(BusId::MASTER, vec![EqBand::high_pass(0.0)]), Err(EffectError::FrequencyOutOfRange(0.0)));
+    assert_eq!(mixer.set_bus_eq(BusId::MASTER, vec![EqBand::peaking(1000.0, -1.0, 3.0)]), Err(EffectError::QOutOfRange(-1.0)));
+    assert_eq!(mixer.set_bus_eq(BusId::MASTER, vec![EqBand::low_shelf(100.0, 60.0)]), Err(EffectError::GainOutOfRange(60.0)));
+    let compressor = Compressor::new();
+    assert_eq!(mixer.set_bus_compressor(BusId::MASTER, Some(compressor.with_attack_ms(f32::INFINITY))), Err(EffectError::NotFinite));
+    assert_eq!(mixer.set_bus_compressor(BusId::MASTER, Some(compressor.with_ratio(0.5))), Err(EffectError::RatioOutOfRange(0.5)));
+    assert_eq!(mixer.set_bus_compressor(BusId::MASTER, Some(compressor.with_threshold_db(6.0))), Err(EffectError::ThresholdOutOfRange(6.0)));
+    assert_eq!(mixer.set_bus_compressor(BusId::MASTER, Some(compressor.with_release_ms(-1.0))), Err(EffectError::TimeOutOfRange(-1.0)));
+
+    // nothing invalid reached the audio thread
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.5; 4], PlaybackStyle::Once)));
+    assert_samples(&mixer.render(4), &[0.5; 8]);
+}
+
+#[test]
+fn bus_effects_change_the_render() {
+    let tone: Vec<f32> = (0..4410).map(|i| (i as f32 * 0.3).sin() * 0.9).collect();
+    let mut dry = SoundMixer::offline(SAMPLE_RATE);
+    dry.play(PlaybackBuilder::new().with_sound(mono(&tone, PlaybackStyle::Once)));
+    let dry = dry.render(4410);
+    let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+
+    let mut eq = SoundMixer::offline(SAMPLE_RATE);
+    eq.set_bus_eq(BusId::MASTER, vec![EqBand::low_pass(200.0)]).unwrap();
+    eq.play(PlaybackBuilder::new().with_sound(mono(&tone, PlaybackStyle::Once)));
+    let eq = eq.render(4410);
+    assert!(eq.iter().all(|s| s.is_finite()));
+    assert!(energy(&eq) < energy(&dry) * 0.5);
+
+    let mut compressed = SoundMixer::offline(SAMPLE_RATE);
+    compressed.set_bus_compressor(BusId::MASTER, Some(Compressor::new().with_threshold_db(-20.0).with_attack_ms(0.0))).unwrap();
+    compressed.play(PlaybackBuilder::new().with_sound(mono(&tone, PlaybackStyle::Once)));
+    let compressed = compressed.render(4410);
+    assert!(compressed.iter().all(|s| s.is_finite()));
+    assert!(energy(&compressed) < energy(&dry) * 0.5);
+}
+
+fn queued_mixer(queue_frames: usize) -> SoundMixer {
+    SoundMixer::offline_with_config(SAMPLE_RATE, AudioConfig::new().with_render_ahead(RenderAhead::Frames(queue_frames)))
+}
+
+#[test]
+fn render_ahead_queue_hands_out_whole_frames() {
+    let mut mixer = queued_mixer(4);
+    mixer.play(PlaybackBuilder::new().with_sound(sound(SAMPLE_RATE, 2, &[0.1, -0.1, 0.2, -0.2], PlaybackStyle::Looped)));
+    assert_eq!(mixer.render_ahead(10), 4);
+    assert_eq!(mixer.render_ahead(10), 0);
+    assert_samples(&mixer.render(3), &[0.1, -0.1, 0.2, -0.2, 0.1, -0.1]);
+    assert_eq!(mixer.output_info().underruns, 0);
+
+    // the queue runs dry within the buffer, the rest is silence
+    assert_samples(&mixer.render(3), &[0.2, -0.2, 0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(mixer.output_info().underruns, 1);
+
+    // left and right stay in place after the underrun
+    assert_eq!(mixer.render_ahead(2), 2);
+    assert_samples(&mixer.render(2), &[0.1, -0.1, 0.2, -0.2]);
+    assert_eq!(mixer.output_info().underruns, 1);
+}
+
+#[test]
+fn clock_latency_counts_the_device_buffer_and_the_queue() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    mixer.render(5);
+    assert_eq!(mixer.clock().latency_frames(), 5);
+    assert_eq!(mixer.output_info().latency_frames(), 5);
+
+    let mut mixer = queued_mixer(8);
+    let clock = mixer.clock();
+    assert_eq!(clock.latency_frames(), 8);
+    mixer.render_ahead(8);
+    mixer.render(3);
+    assert_eq!(clock.latency_frames(), 3 + 8);
+    assert_eq!(mixer.output_info().latency_frames(), 3 + 8);
+    assert_eq!(clock.delivered_frames(), 3);
+    // rendered ahead of what the device got
+    assert_eq!(mixer.current_frame(), 8);
+}
+
+#[test]
+fn initial_volume_sets_the_master_gain() {
+    let config = AudioConfig::new().with_volume(Volume(0.5)).unwrap();
+    let mut mixer = SoundMixer::offline_with_config(SAMPLE_RATE, config);
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.8], PlaybackStyle::Once)));
+    // through the default squared curve
+    assert_samples(&mixer.render(1), &[0.2, 0.2]);
+    assert!(AudioConfig::new().with_volume(Volume(1.5)).is_err());
+}
+
+#[test]
+fn snapshots_round_trip_through_bytes() {
+    let snapshot = MixerSnapshot {
+        voices: vec![
+            VoiceSnapshot {
+                asset: "wind".to_string(),
+                position: usize::MAX,
+                gain: 0.5,
+                pitch: 1.25,
+                looping: true,
+                paused: false,
+                bus: "ambience".to_string(),
+                tag: None
+            },
+            VoiceSnapshot {
+                asset: "line_03".to_string(),
+                position: 7,
+                gain: 1.0,
+                pitch: 1.0,
+                looping: false,
+                paused: true,
+                bus: "master".to_string(),
+                tag: Some("dialogue".to_string())
+            }
+        ],
+        music: Some(MusicSnapshot { track: "theme".to_string(), playlist_index: Some(2), position: 4410 })
+    };
+    let mut bytes = Vec::new();
+    snapshot.write(&mut bytes).unwrap();
+    assert_eq!(MixerSnapshot::from_bytes(&bytes).unwrap(), snapshot);
+    assert!(MixerSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    // only the current version is read
+    for version in [0, 2] {
+        let mut bytes = bytes.clone();
+        bytes[4] = version;
+        assert!(MixerSnapshot::from_bytes(&bytes).is_err());
+    }
+}
+
+#[test]
+fn restore_brings_back_voices_and_music() {
+    let ramp: Vec<f32> = (0..1000).map(|i| i as f32 / 1000.0).collect();
+    let mut wav = Vec::new();
+    mono(&ramp, PlaybackStyle::Once).write_wav(&mut wav).unwrap();
+    let mut bank = SoundBank::new();
+    bank.register("ramp", SoundAsset::from_bytes(wav).with_playback_style(PlaybackStyle::Looped));
+
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let bus = mixer.add_bus("ambience");
+    let pitched = PitchedSource::new(SampleSource::shared(bank.sound("ramp").unwrap()), 2.0);
+    mixer.play(PlaybackBuilder::new()
+        .with_source(pitched)
+        .with_asset("ramp")
+        .with_gain(Gain(0.5))
+        .with_bus(bus)
+        .with_tag("wind"));
+    bank.play(&mut mixer, "ramp").unwrap();
+    let theme = MusicTrack::from_sound("theme", mono(&[0.25; 4000], PlaybackStyle::Looped));
+    let mut music = MusicPlayer::new();
+    music.enqueue(theme.clone());
+    music.play(MusicTransition::Immediate);
+    music.frame(&mut mixer, 0.0);
+    mixer.render(300);
+
+    let mut snapshot = mixer.snapshot().unwrap();
+    snapshot.music = music.snapshot();
+    assert_eq!(snapshot.voices.len(), 2);
+    assert!(snapshot.voices.iter().any(|voice| voice.pitch == 2.0 && voice.bus == "ambience"));
+    assert_eq!(snapshot.music.as_ref().map(|music| music.position), Some(300));
+    let mut bytes = Vec::new();
+    snapshot.write(&mut bytes).unwrap();
+    let saved = MixerSnapshot::from_bytes(&bytes).unwrap();
+
+    let mut restored = SoundMixer::offline(SAMPLE_RATE);
+    restored.add_bus("ambience");
+    assert_eq!(restored.restore(&saved, &mut bank).unwrap().len(), 2);
+    let mut restored_music = MusicPlayer::new();
+    restored_music.enqueue(theme);
+    assert!(restored_music.restore(saved.music.as_ref().unwrap()));
+    restored_music.frame(&mut restored, 0.0);
+
+    let mut again = restored.snapshot().unwrap();
+    again.music = restored_music.snapshot();
+    assert_eq!(again, snapshot);
+}
+
+#[test]
+fn spectrum_peaks_at_the_played_frequency() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let analyzer = mixer.enable_spectrum(1024);
+    mixer.play(PlaybackBuilder::new().with_source(Oscillator::new(Waveform::Sine, 1000.0)));
+    mixer.render(4096);
+
+    let magnitudes = analyzer.magnitudes_db();
+    assert_eq!(magnitudes.len(), analyzer.bin_count());
+    let loudest = (0..magnitudes.len()).max_by(|a, b| magnitudes[*a].total_cmp(&magnitudes[*b])).unwrap();
+    assert!((analyzer.bin_frequency(loudest) - 1000.0).abs() < analyzer.bin_frequency(1), "{}", loudest);
+
+    assert_eq!(normalize_db(-30.0, -60.0), Some(0.5));
+    assert_eq!(normalize_db(-90.0, -60.0), Some(0.0));
+    assert_eq!(normalize_db(-30.0, 0.0), None);
+    assert_eq!(normalize_db(-30.0, f32::NAN), None);
+}
+
+#[test]
+fn ducking_attacks_holds_and_releases() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let music = mixer.add_bus("music");
+    let dialogue = mixer.add_bus("dialogue");
+    mixer.add_ducking(Ducking::new(DuckTrigger::Bus(dialogue), music)
+        .with_attenuation_db(12.0)
+        .with_attack_ms(10.0)
+        .with_hold_ms(100.0)
+        .with_release_ms(50.0));
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.5], PlaybackStyle::Looped)).with_bus(music));
+    // 200ms of dialogue
+    mixer.play(PlaybackBuilder::new().with_sound(mono(&[0.1; 8820], PlaybackStyle::Once)).with_bus(dialogue));
+    let left: Vec<f32> = mixer.render(30000).iter().step_by(2).cloned().collect();
+    let duck_gain = |frame: usize, dialogue: f32| (left[frame] - dialogue) / 0.5;
+    let attenuation = 10f32.powf(-12.0 / 20.0);
+
+    // the attack takes a few milliseconds instead of cutting the music down at once
+    assert!(duck_gain(0, 0.1) > 0.99);
+    assert!(duck_gain(200, 0.1) > attenuation + 0.1);
+    assert!((duck_gain(2500, 0.1) - attenuation).abs() < 0.01);
+    // still held down after the dialogue ended
+    assert!((duck_gain(8820 + 4000, 0.0) - attenuation).abs() < 0.01);
+    // then released
+    assert!(duck_gain(8820 + 4410 + 1000, 0.0) > attenuation + 0.1);
+    assert!(duck_gain(29999, 0.0) > 0.99);
+}
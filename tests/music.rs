@@ -0,0 +1,126 @@
+//! Music player tests, tracks are constant levels so the mix shows which one plays.
+use rom_media_rs::audio::{SoundMixer, Sound, MusicPlayer, MusicTrack, MusicTransition, RepeatMode};
+use rom_media_rs::audio::mixer::PlaybackStyle;
+
+const SAMPLE_RATE: f32 = 44100.0;
+
+fn track(name: &str, level: f32, length_ms: usize) -> MusicTrack {
+    let frames = length_ms * SAMPLE_RATE as usize / 1000;
+    MusicTrack::from_sound(name, Sound {
+        sample_rate: SAMPLE_RATE,
+        channels: 1,
+        samples: vec![level; frames],
+        playback_style: PlaybackStyle::Once
+    })
+}
+
+fn player(tracks: &[MusicTrack]) -> MusicPlayer {
+    let mut music = MusicPlayer::new();
+    for track in tracks {
+        music.enqueue(track.clone());
+    }
+    music
+}
+
+/// advances the player and then the mixer by `ms` milliseconds, returns the last rendered sample
+fn step(music: &mut MusicPlayer, mixer: &mut SoundMixer, ms: usize) -> f32 {
+    music.frame(mixer, ms as f32);
+    let samples = mixer.render(ms * SAMPLE_RATE as usize / 1000);
+    samples[samples.len() - 1]
+}
+
+/// names of the tracks played over `steps` updates of 10ms, each one listed once
+fn played(music: &mut MusicPlayer, mixer: &mut SoundMixer, steps: usize) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for _ in 0..steps {
+        step(music, mixer, 10);
+        if let Some(name) = music.current_track() {
+            if names.last().map(String::as_str) != Some(name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+#[test]
+fn playlist_plays_in_order_and_stops() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let mut music = player(&[track("a", 0.1, 100), track("b", 0.2, 100), track("c", 0.3, 100)]);
+    music.set_repeat(RepeatMode::Off);
+    music.play(MusicTransition::Immediate);
+    assert_eq!(step(&mut music, &mut mixer, 10), 0.1);
+
+    assert_eq!(played(&mut music, &mut mixer, 50), ["a", "b", "c"]);
+    assert!(!music.is_playing());
+    assert_eq!(step(&mut music, &mut mixer, 10), 0.0);
+}
+
+#[test]
+fn repeat_and_shuffle_keep_the_playlist_going() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let mut music = player(&[track("a", 0.1, 50), track("b", 0.2, 50), track("c", 0.3, 50)]);
+    music.set_shuffle(true);
+    music.play(MusicTransition::Immediate);
+    let names = played(&mut music, &mut mixer, 100);
+    assert!(names.len() >= 12, "{:?}", names);
+    assert!(["a", "b", "c"].iter().all(|name| names.iter().any(|played| played == name)));
+    assert!(music.is_playing());
+
+    music.set_repeat(RepeatMode::One);
+    step(&mut music, &mut mixer, 10);
+    assert_eq!(played(&mut music, &mut mixer, 50).len(), 1);
+}
+
+#[test]
+fn crossfade_blends_the_tracks() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let mut music = player(&[track("loud", 0.5, 2000), track("quiet", 0.25, 2000)]);
+    music.set_crossfade_ms(500);
+    music.play(MusicTransition::Immediate);
+    assert_eq!(step(&mut music, &mut mixer, 100), 0.5);
+
+    music.next(MusicTransition::Crossfade);
+    for _ in 0..4 {
+        // both tracks play, partly faded
+        let level = step(&mut music, &mut mixer, 100);
+        assert!(level > 0.0 && level < 0.5, "{}", level);
+        assert_eq!(mixer.debug_info().unwrap().voices.len(), 2);
+    }
+    assert_eq!(music.current_track(), Some("quiet"));
+    step(&mut music, &mut mixer, 100);
+    step(&mut music, &mut mixer, 100);
+    assert!((step(&mut music, &mut mixer, 100) - 0.25).abs() < 1e-6);
+    assert_eq!(mixer.debug_info().unwrap().voices.len(), 1);
+}
+
+#[test]
+fn loop_boundary_waits_for_the_end_of_the_track() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let mut music = player(&[track("a", 0.1, 500), track("b", 0.2, 500)]);
+    music.set_repeat(RepeatMode::One);
+    music.play(MusicTransition::Immediate);
+    step(&mut music, &mut mixer, 100);
+
+    music.next(MusicTransition::AtLoopBoundary);
+    for _ in 0..4 {
+        assert_eq!(step(&mut music, &mut mixer, 100), 0.1);
+    }
+    assert_eq!(step(&mut music, &mut mixer, 100), 0.2);
+    assert_eq!(music.current_track(), Some("b"));
+}
+
+#[test]
+fn tracks_shorter_than_the_crossfade_still_play() {
+    let mut mixer = SoundMixer::offline(SAMPLE_RATE);
+    let mut music = player(&[track("a", 0.1, 100), track("b", 0.2, 100)]);
+    music.set_crossfade_ms(1000);
+    music.play(MusicTransition::Immediate);
+    // the crossfade into "b" starts halfway through "a"
+    for _ in 0..6 {
+        step(&mut music, &mut mixer, 10);
+        assert_eq!(music.current_track(), Some("a"));
+    }
+    step(&mut music, &mut mixer, 10);
+    assert_eq!(music.current_track(), Some("b"));
+}
@@ -0,0 +1,52 @@
+//! Sound source tests, sources are pulled directly without a mixer.
+use rom_media_rs::audio::{Sound, SoundSource, SourceState, SampleSource, PitchedSource, Oscillator, Waveform};
+use rom_media_rs::audio::mixer::PlaybackStyle;
+
+fn ramp(frames: usize) -> SampleSource {
+    SampleSource::new(Sound {
+        sample_rate: 44100.0,
+        channels: 1,
+        samples: (0..frames).map(|i| i as f32).collect(),
+        playback_style: PlaybackStyle::Once
+    })
+}
+
+fn pull(source: &mut dyn SoundSource, frames: usize) -> Vec<f32> {
+    let mut frame = [0.0f32];
+    (0..frames)
+        .map(|_| {
+            assert_eq!(source.next_frame(&mut frame), SourceState::Ready);
+            frame[0]
+        })
+        .collect()
+}
+
+#[test]
+fn pitched_position_is_the_next_frame_played() {
+    let mut faster = PitchedSource::new(ramp(100), 2.0);
+    assert_eq!(faster.position(), Some(0));
+    assert_eq!(pull(&mut faster, 10), (0..10).map(|i| i as f32 * 2.0).collect::<Vec<_>>());
+    assert_eq!(faster.position(), Some(20));
+    assert_eq!(pull(&mut faster, 1), [20.0]);
+
+    let mut slower = PitchedSource::new(ramp(100), 0.5);
+    pull(&mut slower, 10);
+    assert_eq!(slower.position(), Some(5));
+    assert_eq!(pull(&mut slower, 1), [5.0]);
+
+    assert!(slower.seek(30));
+    assert_eq!(slower.position(), Some(30));
+    assert_eq!(pull(&mut slower, 2), [30.0, 30.5]);
+}
+
+#[test]
+fn oscillator_keeps_time_over_long_notes() {
+    // an `f32` sum of 1 / 44100 drifts and finally stops growing after about 512 seconds
+    let frames = 600 * 44100;
+    let mut oscillator = Oscillator::new(Waveform::Square, 100.0).with_duration(600.0);
+    let mut frame = [0.0f32];
+    for _ in 0..frames {
+        assert_eq!(oscillator.next_frame(&mut frame), SourceState::Ready);
+    }
+    assert_eq!(oscillator.next_frame(&mut frame), SourceState::Finished);
+}
@@ -0,0 +1,126 @@
+//! Tracker module loading tests, modules are built in memory and rendered without an output device.
+use std::sync::Arc;
+use rom_media_rs::audio::{Module, ModuleFormat, ModulePlayer, SoundSource, SourceState};
+
+/// A 4 channel MOD with one empty 64 row pattern played twice.
+fn build_mod() -> Vec<u8> {
+    let mut bytes = vec![0u8; 20];
+    bytes.extend(vec![0u8; 31 * 30]);
+    // song length and restart position
+    bytes.push(2);
+    bytes.push(0);
+    bytes.extend(vec![0u8; 128]);
+    bytes.extend(b"M.K.");
+    bytes.extend(vec![0u8; 64 * 4 * 4]);
+    bytes
+}
+
+/// A single channel S3M with one empty pattern.
+fn build_s3m() -> Vec<u8> {
+    let mut bytes = vec![0u8; 0x70];
+    // order, instrument and pattern counts
+    bytes[0x20] = 2;
+    bytes[0x24] = 1;
+    bytes[0x2C..0x30].copy_from_slice(b"SCRM");
+    bytes[0x30] = 64;
+    bytes[0x31] = 6;
+    bytes[0x32] = 125;
+    bytes[0x33] = 0x30;
+    bytes[0x40..0x60].copy_from_slice(&[0xFF; 32]);
+    bytes[0x40] = 0;
+    // orders, then the pattern parapointer
+    bytes[0x60] = 0;
+    bytes[0x61] = 0xFF;
+    bytes[0x62] = 0x70 / 16;
+    bytes.extend([64, 0]);
+    bytes.extend(vec![0u8; 64]);
+    bytes
+}
+
+/// A single channel XM with one empty one row pattern at speed 1.
+fn build_xm(song_length: u16, restart_position: u16) -> Vec<u8> {
+    let mut bytes = b"Extended Module: ".to_vec();
+    bytes.extend(vec![0u8; 20]);
+    bytes.push(0x1A);
+    bytes.extend(vec![0u8; 20]);
+    bytes.extend(0x0104u16.to_le_bytes());
+    bytes.extend(276u32.to_le_bytes());
+    for value in [song_length, restart_position, 1, 1, 0, 1, 1, 125] {
+        bytes.extend(value.to_le_bytes());
+    }
+    bytes.extend(vec![0u8; 256]);
+    // pattern header without packed data
+    bytes.extend(9u32.to_le_bytes());
+    bytes.push(0);
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(0u16.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn well_formed_modules_load() {
+    assert_eq!(Module::from_bytes(&build_mod()).unwrap().format, ModuleFormat::Mod);
+    assert_eq!(Module::from_bytes(&build_s3m()).unwrap().format, ModuleFormat::S3m);
+    assert_eq!(Module::from_bytes(&build_xm(1, 0)).unwrap().format, ModuleFormat::Xm);
+}
+
+#[test]
+fn truncated_mod_is_rejected() {
+    let bytes = build_mod();
+    assert!(Module::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    // the order table references patterns that are not in the file
+    let mut bytes = build_mod();
+    bytes[20 + 31 * 30 + 2] = 5;
+    assert!(Module::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn malformed_s3m_is_rejected() {
+    let bytes = build_s3m();
+    assert!(Module::from_bytes(&bytes[..0x60]).is_err());
+    assert!(Module::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let mut bytes = build_s3m();
+    bytes[0x40] = 0xFF;
+    assert!(Module::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn malformed_xm_is_rejected() {
+    let bytes = build_xm(1, 0);
+    assert!(Module::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Module::from_bytes(&bytes[..100]).is_err());
+    let mut bytes = build_xm(1, 0);
+    bytes[58] = 0x03;
+    assert!(Module::from_bytes(&bytes).is_err());
+    let mut bytes = build_xm(1, 0);
+    bytes[68] = 0;
+    assert!(Module::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn xm_restart_past_the_order_table_loops_from_the_start() {
+    let module = Module::from_bytes(&build_xm(300, 280)).unwrap();
+    assert_eq!(module.order_count(), 256);
+    let mut player = ModulePlayer::new(Arc::new(module)).with_looping(true);
+    let position = player.position();
+    let mut frame = [0.0f32; 2];
+    while position.loops() < 2 {
+        assert_eq!(player.next_frame(&mut frame), SourceState::Ready);
+    }
+    assert_eq!(position.order(), 0);
+    assert!(!position.is_finished());
+}
+
+#[test]
+fn seeking_past_the_end_of_a_looping_module_wraps_into_the_loop() {
+    // two one row orders of 882 frames, looping back to the second
+    let module = Arc::new(Module::from_bytes(&build_xm(2, 1)).unwrap());
+    let mut player = ModulePlayer::new(module.clone()).with_looping(true);
+    assert!(player.seek(882 * 1000 + 100));
+    assert_eq!(SoundSource::position(&player), Some(882 + 100));
+
+    let mut player = ModulePlayer::new(module);
+    assert!(player.seek(882 * 1000));
+    let mut frame = [0.0f32; 2];
+    assert_eq!(player.next_frame(&mut frame), SourceState::Finished);
+}
@@ -0,0 +1,144 @@
+//! WAV decoding tests, each encoding is checked against a small file built in memory.
+use rom_media_rs::audio::Sound;
+
+const SAMPLE_RATE: u32 = 8000;
+
+/// RIFF WAV with a `fmt ` chunk made of the given fields plus `extra`, then a `data` chunk
+fn wav(tag: u16, channels: u16, block_align: u16, bits_per_sample: u16, extra: Option<&[u8]>, data: &[u8]) -> Vec<u8> {
+    let mut fmt = Vec::new();
+    fmt.extend(tag.to_le_bytes());
+    fmt.extend(channels.to_le_bytes());
+    fmt.extend(SAMPLE_RATE.to_le_bytes());
+    fmt.extend((SAMPLE_RATE * block_align as u32).to_le_bytes());
+    fmt.extend(block_align.to_le_bytes());
+    fmt.extend(bits_per_sample.to_le_bytes());
+    if let Some(extra) = extra {
+        fmt.extend((extra.len() as u16).to_le_bytes());
+        fmt.extend(extra);
+    }
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend(((4 + 8 + fmt.len() + 8 + data.len()) as u32).to_le_bytes());
+    bytes.extend(b"WAVE");
+    bytes.extend(b"fmt ");
+    bytes.extend((fmt.len() as u32).to_le_bytes());
+    bytes.extend(fmt);
+    bytes.extend(b"data");
+    bytes.extend((data.len() as u32).to_le_bytes());
+    bytes.extend(data);
+    bytes
+}
+
+fn error_of(bytes: &[u8]) -> String {
+    match Sound::from_bytes(bytes) {
+        Ok(_) => panic!("decoded an unsupported file"),
+        Err(error) => error.to_string()
+    }
+}
+
+fn assert_samples(sound: &Sound, expected: &[f32]) {
+    assert_eq!(sound.samples.len(), expected.len(), "{:?} != {:?}", sound.samples, expected);
+    for (a, e) in sound.samples.iter().zip(expected.iter()) {
+        assert!((a - e).abs() < 1e-6, "{:?} != {:?}", sound.samples, expected);
+    }
+}
+
+#[test]
+fn integer_pcm() {
+    let sound = Sound::from_bytes(&wav(1, 1, 1, 8, None, &[0, 128, 192])).unwrap();
+    assert_eq!(sound.sample_rate, SAMPLE_RATE as f32);
+    assert_samples(&sound, &[-1.0, 0.0, 0.5]);
+
+    let data: Vec<u8> = [i16::MIN, 0, 16384].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let sound = Sound::from_bytes(&wav(1, 2, 4, 16, None, &data)).unwrap();
+    // the odd sample out doesn't make a whole stereo frame
+    assert_eq!(sound.channels, 2);
+    assert_samples(&sound, &[-1.0, 0.0]);
+
+    let sound = Sound::from_bytes(&wav(1, 1, 3, 24, None, &[0x00, 0x00, 0x40, 0x00, 0x00, 0xC0])).unwrap();
+    assert_samples(&sound, &[0.5, -0.5]);
+
+    let data: Vec<u8> = [0x4000_0000i32, i32::MIN].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let sound = Sound::from_bytes(&wav(1, 1, 4, 32, None, &data)).unwrap();
+    assert_samples(&sound, &[0.5, -1.0]);
+}
+
+#[test]
+fn float_pcm() {
+    let data: Vec<u8> = [0.25f32, -0.75].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let sound = Sound::from_bytes(&wav(3, 1, 4, 32, Some(&[]), &data)).unwrap();
+    assert_samples(&sound, &[0.25, -0.75]);
+
+    let data: Vec<u8> = [-0.5f64].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let sound = Sound::from_bytes(&wav(3, 1, 8, 64, Some(&[]), &data)).unwrap();
+    assert_samples(&sound, &[-0.5]);
+}
+
+#[test]
+fn extensible_header_uses_the_sub_format() {
+    let mut extra = Vec::new();
+    extra.extend(16u16.to_le_bytes());
+    extra.extend(4u32.to_le_bytes());
+    // KSDATAFORMAT_SUBTYPE_PCM
+    extra.extend([0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+    let data: Vec<u8> = [16384i16, -16384].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let sound = Sound::from_bytes(&wav(0xFFFE, 1, 2, 16, Some(&extra), &data)).unwrap();
+    assert_samples(&sound, &[0.5, -0.5]);
+
+    assert!(Sound::from_bytes(&wav(0xFFFE, 1, 2, 16, Some(&extra[..4]), &data)).is_err());
+}
+
+#[test]
+fn ima_adpcm() {
+    // predictor 1000 at step index 0, then 8 nibbles low nibble first
+    let mut block = Vec::new();
+    block.extend(1000i16.to_le_bytes());
+    block.extend([0, 0]);
+    block.extend([0x04, 0x00, 0x00, 0x00]);
+    let sound = Sound::from_bytes(&wav(0x11, 1, 8, 4, Some(&[]), &block)).unwrap();
+    let expected: Vec<f32> = [1000, 1007, 1008, 1009, 1009, 1009, 1009, 1009, 1009].iter()
+        .map(|s| *s as f32 / 32768.0)
+        .collect();
+    assert_samples(&sound, &expected);
+}
+
+#[test]
+fn ms_adpcm() {
+    // predictor 0, delta 16, then the two history samples newest first
+    let mut block = vec![0];
+    block.extend(16i16.to_le_bytes());
+    block.extend(100i16.to_le_bytes());
+    block.extend(50i16.to_le_bytes());
+    // high nibble first
+    block.push(0x10);
+    let sound = Sound::from_bytes(&wav(0x02, 1, 8, 4, None, &block)).unwrap();
+    let expected: Vec<f32> = [50, 100, 116, 116].iter().map(|s| *s as f32 / 32768.0).collect();
+    assert_samples(&sound, &expected);
+}
+
+#[test]
+fn corrupt_ms_adpcm_does_not_overflow() {
+    let mut block = vec![0];
+    block.extend(i16::MAX.to_le_bytes());
+    block.extend(i16::MAX.to_le_bytes());
+    block.extend(i16::MAX.to_le_bytes());
+    block.extend(vec![0x77; 249]);
+    let sound = Sound::from_bytes(&wav(0x02, 1, 256, 4, None, &block)).unwrap();
+    assert_eq!(sound.samples.len(), 2 + 249 * 2);
+    assert!(sound.samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+
+    // an unknown predictor is an error, not a panic
+    block[0] = 7;
+    assert!(Sound::from_bytes(&wav(0x02, 1, 256, 4, None, &block)).is_err());
+}
+
+#[test]
+fn unsupported_formats_are_named() {
+    let error = error_of(&wav(0x55, 1, 1, 0, None, &[0; 4]));
+    assert!(error.contains("MPEG Layer 3"), "{}", error);
+    let error = error_of(&wav(1, 1, 2, 12, None, &[0; 4]));
+    assert!(error.contains("bit depth"), "{}", error);
+    let error = error_of(&wav(1, 3, 6, 16, None, &[0; 6]));
+    assert!(error.contains("channel count"), "{}", error);
+    assert!(Sound::from_bytes(&wav(1, 1, 2, 16, None, &[0; 4])[..20]).is_err());
+}